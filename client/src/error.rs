@@ -0,0 +1,44 @@
+use thiserror::Error;
+
+use paystream::{error::PaystreamError, event::EventError};
+use solana_program::{program_error::ProgramError, pubkey::Pubkey};
+use solana_sdk::transport::TransportError;
+
+#[derive(Error, Debug)]
+pub enum ClientError {
+    /// The cluster couldn't be reached or rejected the transaction
+    #[error(transparent)]
+    Transport(#[from] TransportError),
+    #[error(transparent)]
+    Program(#[from] ProgramError),
+    /// A stream couldn't be encoded to work out its size
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    /// The transaction logged an event that doesn't decode
+    #[error(transparent)]
+    Event(#[from] EventError),
+    #[error("Stream {0} not found")]
+    StreamNotFound(Pubkey),
+    /// The program skipped the stream of a batch with the error its `BatchStreamFailed` event
+    /// reported
+    #[error("Stream {0} was not created: {1}")]
+    StreamNotCreated(Pubkey, ProgramError),
+    /// The logs of the batch have no event for the stream, which happens when they are truncated
+    #[error("Outcome of batch stream {0} is unknown")]
+    OutcomeUnknown(Pubkey),
+    #[error("Stream {0} already exists")]
+    StreamExists(Pubkey),
+    /// The transaction carrying the stream of a batch failed
+    #[error("Batch creating stream {0} failed: {1}")]
+    BatchFailed(Pubkey, String),
+    #[error("Sysvar {0} not found")]
+    SysvarNotFound(Pubkey),
+    #[error("Token stream needs the {0} token account")]
+    MissingTokenAccount(&'static str),
+}
+
+impl From<PaystreamError> for ClientError {
+    fn from(e: PaystreamError) -> Self {
+        ClientError::Program(e.into())
+    }
+}
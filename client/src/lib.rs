@@ -0,0 +1,544 @@
+//! Client for the paystream program, covering the whole life of a stream: deriving its
+//! addresses, funding it, sending the instructions and reading its state back, including what
+//! can be withdrawn right now.
+
+pub mod error;
+pub mod rpc;
+
+pub use error::ClientError;
+pub use rpc::PaystreamRpc;
+
+use paystream::{
+    event::{decode_logs, PaystreamEvent},
+    find_stream_address, find_vault_authority_address,
+    instruction::{self, BatchStream, PaystreamInstruction, StreamTerms},
+    state::{StreamAccount, TopUpMode},
+};
+use solana_program::{
+    clock::Clock,
+    instruction::Instruction,
+    program_error::ProgramError,
+    pubkey::Pubkey,
+    rent::Rent,
+    sysvar::{self, Sysvar},
+};
+use solana_sdk::{
+    account::from_account,
+    signature::{Keypair, Signer},
+    transaction::Transaction,
+};
+
+/// Token accounts paid out when cancelling a token stream
+#[derive(Debug, Clone, Default)]
+pub struct CancelTokenAccounts {
+    /// Token account of the payee, unused by split streams
+    pub payee: Pubkey,
+    /// Token account of the payer, refunded the unvested rest
+    pub payer: Pubkey,
+    /// Token account of each recipient of a split stream, in order
+    pub recipients: Vec<Pubkey>,
+}
+
+/// Sends the instructions of the program through `R`, paying the fees with `fee_payer`
+pub struct PaystreamClient<R> {
+    rpc: R,
+    program_id: Pubkey,
+    fee_payer: Keypair,
+}
+
+impl<R: PaystreamRpc> PaystreamClient<R> {
+    pub fn new(rpc: R, program_id: Pubkey, fee_payer: Keypair) -> Self {
+        Self {
+            rpc,
+            program_id,
+            fee_payer,
+        }
+    }
+
+    pub fn rpc(&mut self) -> &mut R {
+        &mut self.rpc
+    }
+
+    pub fn program_id(&self) -> Pubkey {
+        self.program_id
+    }
+
+    pub fn stream_address(&self, payer: &Pubkey, payee: &Pubkey, seed: u64) -> Pubkey {
+        find_stream_address(&self.program_id, payer, payee, seed).0
+    }
+
+    /// The address of a stream worked out from its state, which holds the payee it was created
+    /// for, the address doesn't follow the stream to a new payee
+    pub fn address_of(&self, stream_data: &StreamAccount) -> Pubkey {
+        self.stream_address(
+            &stream_data.payer_pubkey,
+            &stream_data.original_payee_pubkey,
+            stream_data.seed,
+        )
+    }
+
+    /// The PDA the vault token account of a token stream has to be owned by
+    pub fn vault_authority_address(&self, stream: &Pubkey) -> Pubkey {
+        find_vault_authority_address(&self.program_id, stream).0
+    }
+
+    pub async fn get_clock(&mut self) -> Result<Clock, ClientError> {
+        self.get_sysvar(sysvar::clock::id()).await
+    }
+
+    pub async fn get_rent(&mut self) -> Result<Rent, ClientError> {
+        self.get_sysvar(sysvar::rent::id()).await
+    }
+
+    async fn get_sysvar<S: Sysvar>(&mut self, id: Pubkey) -> Result<S, ClientError> {
+        let account = self
+            .rpc
+            .get_account(&id)
+            .await?
+            .ok_or(ClientError::SysvarNotFound(id))?;
+        from_account(&account).ok_or(ClientError::SysvarNotFound(id))
+    }
+
+    /// Rent the account of a stream on `stream_data` terms costs the payer on top of the amount
+    pub async fn stream_rent(&mut self, stream_data: &StreamAccount) -> Result<u64, ClientError> {
+        let space = stream_data.packed_len()?;
+        Ok(self.get_rent().await?.minimum_balance(space))
+    }
+
+    pub async fn get_stream(&mut self, stream: &Pubkey) -> Result<StreamAccount, ClientError> {
+        let account = self
+            .rpc
+            .get_account(stream)
+            .await?
+            .ok_or(ClientError::StreamNotFound(*stream))?;
+        Ok(StreamAccount::load(&account.data)?)
+    }
+
+    /// What `payee`, the payee of the stream or one of its recipients, could withdraw right now
+    pub async fn withdrawable_amount(
+        &mut self,
+        stream: &Pubkey,
+        payee: &Pubkey,
+    ) -> Result<u64, ClientError> {
+        let stream_data = self.get_stream(stream).await?;
+        let now = stream_data.now(&self.get_clock().await?)?;
+        Ok(stream_data.withdrawable_amount_for(payee, now)?)
+    }
+
+    /// Sign `instructions` with the fee payer and `signers` and send them in one transaction
+    pub async fn send(
+        &mut self,
+        instructions: &[Instruction],
+        signers: &[&Keypair],
+    ) -> Result<(), ClientError> {
+        self.send_for_logs(instructions, signers).await?;
+        Ok(())
+    }
+
+    /// `send`, returning the log messages of the transaction
+    async fn send_for_logs(
+        &mut self,
+        instructions: &[Instruction],
+        signers: &[&Keypair],
+    ) -> Result<Vec<String>, ClientError> {
+        let blockhash = self.rpc.get_recent_blockhash().await?;
+        let mut all_signers = vec![&self.fee_payer];
+        for signer in signers {
+            if all_signers.iter().all(|known| known.pubkey() != signer.pubkey()) {
+                all_signers.push(signer);
+            }
+        }
+        let transaction = Transaction::new_signed_with_payer(
+            instructions,
+            Some(&self.fee_payer.pubkey()),
+            &all_signers,
+            blockhash,
+        );
+
+        Ok(self.rpc.send_transaction(transaction).await?)
+    }
+
+    /// Create a lamport stream on `terms`, returning its address
+    pub async fn create_stream(
+        &mut self,
+        payer: &Keypair,
+        terms: StreamTerms,
+    ) -> Result<Pubkey, ClientError> {
+        let stream = self.stream_address(&terms.payer_pubkey, &terms.payee_pubkey, terms.seed);
+        let instruction = instruction::create(
+            self.program_id,
+            PaystreamInstruction::Create { terms },
+            stream,
+            payer.pubkey(),
+        )?;
+        self.send(&[instruction], &[payer]).await?;
+
+        Ok(stream)
+    }
+
+    /// Create a token stream on `terms`, returning its address. The vault has to be a token
+    /// account for `mint` owned by `vault_authority_address`.
+    pub async fn create_token_stream(
+        &mut self,
+        payer: &Keypair,
+        terms: StreamTerms,
+        vault: Pubkey,
+        payer_token_account: Pubkey,
+        mint: Pubkey,
+    ) -> Result<Pubkey, ClientError> {
+        let stream = self.stream_address(&terms.payer_pubkey, &terms.payee_pubkey, terms.seed);
+        let instruction = instruction::create_token_stream(
+            self.program_id,
+            PaystreamInstruction::CreateTokenStream { terms },
+            stream,
+            vault,
+            payer_token_account,
+            payer.pubkey(),
+            mint,
+        )?;
+        self.send(&[instruction], &[payer]).await?;
+
+        Ok(stream)
+    }
+
+    /// Create `streams` on the `terms` they share in as few transactions as needed, returning
+    /// the outcome of each stream in order, as told by the events of the program. Streams the
+    /// program skipped, already existed or were in a transaction that failed are errors, the
+    /// others their address.
+    pub async fn create_batch(
+        &mut self,
+        payer: &Keypair,
+        terms: StreamTerms,
+        streams: Vec<BatchStream>,
+    ) -> Result<Vec<Result<Pubkey, ClientError>>, ClientError> {
+        let addresses: Vec<Pubkey> = streams
+            .iter()
+            .map(|stream| self.stream_address(&terms.payer_pubkey, &stream.payee_pubkey, stream.seed))
+            .collect();
+        let instructions = instruction::create_batches(
+            self.program_id,
+            PaystreamInstruction::CreateBatch { terms, streams },
+        )?;
+
+        let mut results = Vec::with_capacity(addresses.len());
+        let mut streams = addresses.into_iter();
+        for instruction in instructions {
+            // The stream accounts follow the payer, rent, clock and system program
+            let batch: Vec<Pubkey> = streams.by_ref().take(instruction.accounts.len() - 4).collect();
+            let events = match self.send_for_logs(&[instruction], &[payer]).await {
+                Ok(logs) => decode_logs(&self.program_id, &logs)?,
+                Err(error) => {
+                    let error = error.to_string();
+                    results.extend(
+                        batch
+                            .into_iter()
+                            .map(|stream| Err(ClientError::BatchFailed(stream, error.clone()))),
+                    );
+                    continue;
+                }
+            };
+
+            for (index, stream) in batch.into_iter().enumerate() {
+                let outcome = events.iter().find_map(|event| match *event {
+                    PaystreamEvent::BatchStreamCreated { index: created, .. }
+                        if created as usize == index =>
+                    {
+                        Some(Ok(stream))
+                    }
+                    PaystreamEvent::BatchStreamFailed {
+                        index: failed,
+                        error,
+                        ..
+                    } if failed as usize == index => Some(Err(match ProgramError::from(error) {
+                        ProgramError::AccountAlreadyInitialized => ClientError::StreamExists(stream),
+                        error => ClientError::StreamNotCreated(stream, error),
+                    })),
+                    _ => None,
+                });
+                results.push(outcome.unwrap_or(Err(ClientError::OutcomeUnknown(stream))));
+            }
+        }
+
+        Ok(results)
+    }
+
+    /// Withdraw up to `amount` to `destination`, the payee itself if not given. Token streams
+    /// need the token account to pay into.
+    pub async fn withdraw(
+        &mut self,
+        stream: &Pubkey,
+        payee: &Keypair,
+        amount: u64,
+        destination: Option<Pubkey>,
+    ) -> Result<(), ClientError> {
+        let stream_data = self.get_stream(stream).await?;
+        let instruction_data = PaystreamInstruction::Withdrawal { amount };
+        let instruction = if stream_data.is_token_stream() {
+            instruction::withdrawal_token(
+                self.program_id,
+                instruction_data,
+                *stream,
+                payee.pubkey(),
+                stream_data.vault_pubkey,
+                destination.ok_or(ClientError::MissingTokenAccount("destination"))?,
+            )?
+        } else {
+            instruction::withdrawal(
+                self.program_id,
+                instruction_data,
+                *stream,
+                payee.pubkey(),
+                destination,
+            )?
+        };
+
+        self.send(&[instruction], &[payee]).await
+    }
+
+    /// Pay everything vested to `payee`, the payee of the stream or one of its recipients, with
+    /// `caller` getting the crank tip. Token streams need the token account of the payee, and of
+    /// the caller if there is a tip.
+    pub async fn push_withdrawal(
+        &mut self,
+        stream: &Pubkey,
+        caller: &Keypair,
+        payee: Pubkey,
+        payee_token_account: Option<Pubkey>,
+        caller_token_account: Option<Pubkey>,
+    ) -> Result<(), ClientError> {
+        let stream_data = self.get_stream(stream).await?;
+        let instruction_data = PaystreamInstruction::PushWithdrawal {};
+        let instruction = if stream_data.is_token_stream() {
+            if stream_data.crank_tip > 0 && caller_token_account.is_none() {
+                return Err(ClientError::MissingTokenAccount("caller"));
+            }
+            instruction::push_withdrawal_token(
+                self.program_id,
+                instruction_data,
+                *stream,
+                caller.pubkey(),
+                payee,
+                stream_data.vault_pubkey,
+                payee_token_account.ok_or(ClientError::MissingTokenAccount("payee"))?,
+                caller_token_account,
+            )?
+        } else {
+            instruction::push_withdrawal(
+                self.program_id,
+                instruction_data,
+                *stream,
+                caller.pubkey(),
+                payee,
+            )?
+        };
+
+        self.send(&[instruction], &[caller]).await
+    }
+
+    /// Cancel the stream as `signer`, its payee, payer or authority. Split lamport streams pay
+    /// their recipients directly, token streams need `token_accounts`.
+    pub async fn cancel(
+        &mut self,
+        stream: &Pubkey,
+        signer: &Keypair,
+        token_accounts: Option<CancelTokenAccounts>,
+    ) -> Result<(), ClientError> {
+        let stream_data = self.get_stream(stream).await?;
+        // A recipient cancelling a split stream signs in place of the payee
+        let payee = if stream_data.is_split() && stream_data.is_payee(&signer.pubkey()) {
+            signer.pubkey()
+        } else {
+            stream_data.payee_pubkey
+        };
+        let payer = stream_data.payer_pubkey;
+        let instruction_data = PaystreamInstruction::Cancel {};
+        let instruction = if stream_data.is_token_stream() {
+            let token_accounts =
+                token_accounts.ok_or(ClientError::MissingTokenAccount("cancellation"))?;
+            if stream_data.is_split() {
+                instruction::cancel_split_token(
+                    self.program_id,
+                    instruction_data,
+                    *stream,
+                    payee,
+                    payer,
+                    signer.pubkey(),
+                    stream_data.vault_pubkey,
+                    token_accounts.payer,
+                    &token_accounts.recipients,
+                )?
+            } else {
+                instruction::cancel_token(
+                    self.program_id,
+                    instruction_data,
+                    *stream,
+                    payee,
+                    payer,
+                    signer.pubkey(),
+                    stream_data.vault_pubkey,
+                    token_accounts.payee,
+                    token_accounts.payer,
+                )?
+            }
+        } else if stream_data.is_split() {
+            let recipients: Vec<Pubkey> = stream_data
+                .recipients
+                .iter()
+                .map(|recipient| recipient.pubkey)
+                .collect();
+            instruction::cancel_split(
+                self.program_id,
+                instruction_data,
+                *stream,
+                payee,
+                payer,
+                signer.pubkey(),
+                &recipients,
+            )?
+        } else {
+            instruction::cancel(
+                self.program_id,
+                instruction_data,
+                *stream,
+                payee,
+                payer,
+                signer.pubkey(),
+            )?
+        };
+
+        self.send(&[instruction], &[signer]).await
+    }
+
+    /// Pause the stream as `authority`, its payer, authority or pause authority
+    pub async fn pause(&mut self, stream: &Pubkey, authority: &Keypair) -> Result<(), ClientError> {
+        let instruction = instruction::pause(
+            self.program_id,
+            PaystreamInstruction::Pause {},
+            *stream,
+            authority.pubkey(),
+        )?;
+
+        self.send(&[instruction], &[authority]).await
+    }
+
+    pub async fn resume(&mut self, stream: &Pubkey, authority: &Keypair) -> Result<(), ClientError> {
+        let instruction = instruction::resume(
+            self.program_id,
+            PaystreamInstruction::Resume {},
+            *stream,
+            authority.pubkey(),
+        )?;
+
+        self.send(&[instruction], &[authority]).await
+    }
+
+    /// Deposit `amount` more, funded by `funder`, the payer or authority of the stream. Token
+    /// streams need the token account of the funder.
+    pub async fn top_up(
+        &mut self,
+        stream: &Pubkey,
+        funder: &Keypair,
+        amount: u64,
+        mode: TopUpMode,
+        funder_token_account: Option<Pubkey>,
+    ) -> Result<(), ClientError> {
+        let stream_data = self.get_stream(stream).await?;
+        let instruction_data = PaystreamInstruction::TopUp { amount, mode };
+        let instruction = if stream_data.is_token_stream() {
+            instruction::top_up_token(
+                self.program_id,
+                instruction_data,
+                *stream,
+                funder.pubkey(),
+                funder_token_account.ok_or(ClientError::MissingTokenAccount("funder"))?,
+                stream_data.vault_pubkey,
+            )?
+        } else {
+            instruction::top_up(self.program_id, instruction_data, *stream, funder.pubkey())?
+        };
+
+        self.send(&[instruction], &[funder]).await
+    }
+
+    pub async fn transfer_payee(
+        &mut self,
+        stream: &Pubkey,
+        payee: &Keypair,
+        new_payee: Pubkey,
+    ) -> Result<(), ClientError> {
+        let instruction = instruction::transfer_payee(
+            self.program_id,
+            PaystreamInstruction::TransferPayee { new_payee },
+            *stream,
+            payee.pubkey(),
+        )?;
+
+        self.send(&[instruction], &[payee]).await
+    }
+
+    /// Close a settled stream, returning its lamports, and the vault of a token stream, to
+    /// `recipient`. Token streams need the token account of the payer to sweep anything left in
+    /// the vault to.
+    pub async fn close(
+        &mut self,
+        stream: &Pubkey,
+        payer: &Keypair,
+        recipient: Pubkey,
+        payer_token_account: Option<Pubkey>,
+    ) -> Result<(), ClientError> {
+        let stream_data = self.get_stream(stream).await?;
+        let instruction_data = PaystreamInstruction::Close {};
+        let instruction = if stream_data.is_token_stream() {
+            instruction::close_token(
+                self.program_id,
+                instruction_data,
+                *stream,
+                payer.pubkey(),
+                recipient,
+                stream_data.vault_pubkey,
+                payer_token_account.ok_or(ClientError::MissingTokenAccount("payer"))?,
+            )?
+        } else {
+            instruction::close(self.program_id, instruction_data, *stream, payer.pubkey(), recipient)?
+        };
+
+        self.send(&[instruction], &[payer]).await
+    }
+
+    /// Upgrade a stream of an older layout, returning where it lives afterwards. Streams that
+    /// outgrow their account move to the address for `seed`, `funder` paying the rent of the new
+    /// account and getting that of the old one back.
+    pub async fn migrate(
+        &mut self,
+        stream: &Pubkey,
+        funder: &Keypair,
+        seed: u64,
+    ) -> Result<Pubkey, ClientError> {
+        let account = self
+            .rpc
+            .get_account(stream)
+            .await?
+            .ok_or(ClientError::StreamNotFound(*stream))?;
+        let (stream_data, _) = StreamAccount::load_any(&account.data)?;
+        let space = stream_data.packed_len()?;
+        let new_stream = if space > account.data.len() {
+            Some(self.stream_address(
+                &stream_data.payer_pubkey,
+                &stream_data.original_payee_pubkey,
+                seed,
+            ))
+        } else {
+            None
+        };
+        let instruction = instruction::migrate(
+            self.program_id,
+            PaystreamInstruction::Migrate { seed },
+            *stream,
+            funder.pubkey(),
+            new_stream,
+        )?;
+        self.send(&[instruction], &[funder]).await?;
+
+        Ok(new_stream.unwrap_or(*stream))
+    }
+}
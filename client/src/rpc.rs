@@ -0,0 +1,107 @@
+use async_trait::async_trait;
+use solana_banks_client::BanksClient;
+use solana_sdk::{
+    account::Account, hash::Hash, pubkey::Pubkey, transaction::Transaction,
+    transport::TransportError,
+};
+
+/// What the client needs from a cluster, so it runs against `BanksClient` in tests as well as
+/// the RPC client of a real cluster
+#[async_trait]
+pub trait PaystreamRpc {
+    /// The account at `pubkey`, `None` if there is none
+    async fn get_account(&mut self, pubkey: &Pubkey) -> Result<Option<Account>, TransportError>;
+
+    async fn get_recent_blockhash(&mut self) -> Result<Hash, TransportError>;
+
+    /// Send the signed transaction and wait until it is processed, returning its log messages
+    async fn send_transaction(
+        &mut self,
+        transaction: Transaction,
+    ) -> Result<Vec<String>, TransportError>;
+}
+
+#[async_trait]
+impl PaystreamRpc for BanksClient {
+    async fn get_account(&mut self, pubkey: &Pubkey) -> Result<Option<Account>, TransportError> {
+        BanksClient::get_account(self, *pubkey)
+            .await
+            .map_err(TransportError::from)
+    }
+
+    async fn get_recent_blockhash(&mut self) -> Result<Hash, TransportError> {
+        BanksClient::get_recent_blockhash(self)
+            .await
+            .map_err(TransportError::from)
+    }
+
+    async fn send_transaction(
+        &mut self,
+        transaction: Transaction,
+    ) -> Result<Vec<String>, TransportError> {
+        let processed = self.process_transaction_with_metadata(transaction).await?;
+        processed.result?;
+        Ok(processed
+            .metadata
+            .map(|metadata| metadata.log_messages)
+            .unwrap_or_default())
+    }
+}
+
+#[cfg(feature = "rpc")]
+mod rpc_client {
+    use super::*;
+    use solana_client::rpc_client::RpcClient;
+    use solana_transaction_status::UiTransactionEncoding;
+    use std::sync::Arc;
+
+    /// The RPC client blocks, so every call runs on the blocking thread pool of tokio rather than
+    /// holding up the async runtime while waiting on the cluster
+    #[async_trait]
+    impl PaystreamRpc for Arc<RpcClient> {
+        async fn get_account(&mut self, pubkey: &Pubkey) -> Result<Option<Account>, TransportError> {
+            let pubkey = *pubkey;
+            run_blocking(self, move |client| {
+                Ok(client
+                    .get_account_with_commitment(&pubkey, client.commitment())?
+                    .value)
+            })
+            .await
+        }
+
+        async fn get_recent_blockhash(&mut self) -> Result<Hash, TransportError> {
+            run_blocking(self, |client| {
+                let (blockhash, _) = client.get_recent_blockhash()?;
+                Ok(blockhash)
+            })
+            .await
+        }
+
+        async fn send_transaction(
+            &mut self,
+            transaction: Transaction,
+        ) -> Result<Vec<String>, TransportError> {
+            run_blocking(self, move |client| {
+                let signature = client.send_and_confirm_transaction(&transaction)?;
+                let confirmed = client.get_transaction(&signature, UiTransactionEncoding::Json)?;
+                Ok(confirmed
+                    .transaction
+                    .meta
+                    .and_then(|meta| meta.log_messages)
+                    .unwrap_or_default())
+            })
+            .await
+        }
+    }
+
+    async fn run_blocking<T, F>(client: &Arc<RpcClient>, call: F) -> Result<T, TransportError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&RpcClient) -> Result<T, TransportError> + Send + 'static,
+    {
+        let client = Arc::clone(client);
+        tokio::task::spawn_blocking(move || call(&client))
+            .await
+            .map_err(std::io::Error::from)?
+    }
+}
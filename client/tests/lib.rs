@@ -0,0 +1,155 @@
+use paystream::error::PaystreamError;
+use paystream::processor::Processor;
+use paystream::instruction::{BatchStream, StreamTerms, MAX_BATCH_STREAMS};
+use paystream::state::{StreamStatus, TopUpMode};
+use paystream_client::{ClientError, PaystreamClient};
+use solana_program_test::*;
+use solana_sdk::{
+    account::Account,
+    program_error::ProgramError,
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+};
+
+async fn start_client() -> (ProgramTestContext, PaystreamClient<BanksClient>, Keypair, Keypair) {
+    let program_id = Pubkey::new_unique();
+    let payer_key = Keypair::new();
+    let payee_key = Keypair::new();
+
+    let mut program_test = ProgramTest::new("paystream", program_id, processor!(Processor::process));
+    for key in [&payer_key, &payee_key].iter() {
+        program_test.add_account(
+            key.pubkey(),
+            Account {
+                lamports: 50000000,
+                ..Account::default()
+            },
+        );
+    }
+
+    let ctx = program_test.start_with_context().await;
+    let fee_payer = Keypair::from_bytes(&ctx.payer.to_bytes()).unwrap();
+    let client = PaystreamClient::new(ctx.banks_client.clone(), program_id, fee_payer);
+
+    (ctx, client, payer_key, payee_key)
+}
+
+fn stream_terms(payer_key: &Keypair, payee_key: &Keypair, amount: u64, duration: u64) -> StreamTerms {
+    StreamTerms {
+        payee_pubkey: payee_key.pubkey(),
+        payer_pubkey: payer_key.pubkey(),
+        amount,
+        duration,
+        ..StreamTerms::default()
+    }
+}
+
+async fn get_balance(client: &mut PaystreamClient<BanksClient>, key: &Pubkey) -> u64 {
+    client.rpc().get_balance(*key).await.unwrap()
+}
+
+#[tokio::test]
+async fn should_run_stream_lifecycle_through_client() {
+    let (mut ctx, mut client, payer_key, payee_key) = start_client().await;
+    let amount = 1_000_000;
+    let duration = 100;
+
+    let payer_balance = get_balance(&mut client, &payer_key.pubkey()).await;
+    let stream_key = client
+        .create_stream(&payer_key, stream_terms(&payer_key, &payee_key, amount, duration))
+        .await
+        .unwrap();
+    assert_eq!(stream_key, client.stream_address(&payer_key.pubkey(), &payee_key.pubkey(), 0));
+
+    let stream = client.get_stream(&stream_key).await.unwrap();
+    assert_eq!(client.address_of(&stream), stream_key);
+    assert_eq!(stream.amount_in_lamports, amount);
+    assert_eq!(stream.status, StreamStatus::Active);
+    let rent = client.stream_rent(&stream).await.unwrap();
+    assert_eq!(payer_balance - get_balance(&mut client, &payer_key.pubkey()).await, amount + rent);
+
+    ctx.warp_to_slot(duration / 2).unwrap();
+
+    // The balance worked out off-chain is what the program pays out
+    let withdrawable = client.withdrawable_amount(&stream_key, &payee_key.pubkey()).await.unwrap();
+    assert!(withdrawable > 0 && withdrawable < amount);
+    let payee_balance = get_balance(&mut client, &payee_key.pubkey()).await;
+    client.withdraw(&stream_key, &payee_key, withdrawable, None).await.unwrap();
+    assert_eq!(get_balance(&mut client, &payee_key.pubkey()).await - payee_balance, withdrawable);
+    assert_eq!(client.withdrawable_amount(&stream_key, &payee_key.pubkey()).await.unwrap(), 0);
+
+    client.pause(&stream_key, &payer_key).await.unwrap();
+    assert!(client.get_stream(&stream_key).await.unwrap().is_paused());
+    client.resume(&stream_key, &payer_key).await.unwrap();
+
+    let end_time = client.get_stream(&stream_key).await.unwrap().end_time().unwrap();
+    client
+        .top_up(&stream_key, &payer_key, amount, TopUpMode::ExtendDuration, None)
+        .await
+        .unwrap();
+    let stream = client.get_stream(&stream_key).await.unwrap();
+    assert_eq!(stream.total_amount().unwrap(), Some(amount * 2));
+    assert!(stream.end_time().unwrap() > end_time);
+
+    client.cancel(&stream_key, &payer_key, None).await.unwrap();
+    assert!(client.get_stream(&stream_key).await.unwrap().is_terminated());
+
+    client.close(&stream_key, &payer_key, payer_key.pubkey(), None).await.unwrap();
+    assert!(matches!(
+        client.get_stream(&stream_key).await,
+        Err(ClientError::StreamNotFound(key)) if key == stream_key
+    ));
+}
+
+#[tokio::test]
+async fn should_report_outcome_of_each_batch_stream() {
+    let (_ctx, mut client, payer_key, payee_key) = start_client().await;
+    let existing_key = client
+        .create_stream(&payer_key, stream_terms(&payer_key, &payee_key, 1000, 10))
+        .await
+        .unwrap();
+
+    // More streams than fit one batch: one that exists already, one the payer can't afford
+    let batch_stream = |payee_pubkey, amount| BatchStream {
+        payee_pubkey,
+        amount,
+        duration: 100,
+        seed: 0,
+        start_time: None,
+        cliff_time: None,
+    };
+    let mut streams = vec![
+        batch_stream(payee_key.pubkey(), 1000),
+        batch_stream(Pubkey::new_unique(), 100_000_000),
+    ];
+    streams.extend((0..MAX_BATCH_STREAMS).map(|_| batch_stream(Pubkey::new_unique(), 1000)));
+    let terms = StreamTerms {
+        payer_pubkey: payer_key.pubkey(),
+        ..StreamTerms::default()
+    };
+
+    let results = client.create_batch(&payer_key, terms, streams.clone()).await.unwrap();
+    assert_eq!(results.len(), streams.len());
+    assert!(matches!(&results[0], Err(ClientError::StreamExists(key)) if *key == existing_key));
+    assert!(matches!(
+        &results[1],
+        Err(ClientError::StreamNotCreated(_, error))
+            if *error == ProgramError::from(PaystreamError::InsufficientFunds)
+    ));
+    for (result, stream) in results.iter().zip(&streams).skip(2) {
+        let stream_key = result.as_ref().unwrap();
+        assert_eq!(client.get_stream(stream_key).await.unwrap().payee_pubkey, stream.payee_pubkey);
+    }
+}
+
+#[cfg(feature = "rpc")]
+#[test]
+fn should_build_client_on_rpc_client() {
+    use solana_client::rpc_client::RpcClient;
+    use std::sync::Arc;
+
+    let rpc = Arc::new(RpcClient::new("http://localhost:8899".to_string()));
+    let program_id = Pubkey::new_unique();
+    let client: PaystreamClient<Arc<RpcClient>> = PaystreamClient::new(rpc, program_id, Keypair::new());
+    assert_eq!(client.program_id(), program_id);
+}
@@ -13,6 +13,71 @@ pub enum PaystreamError {
     InvalidPayee,
     #[error("Invalid payer")]
     InvalidPayer,
+    #[error("Invalid vault token account")]
+    InvalidVault,
+    #[error("Invalid mint")]
+    InvalidMint,
+    /// The signing payer account doesn't match the payer of the stream
+    #[error("Payer must sign stream creation")]
+    PayerNotSigner,
+    #[error("Stream duration must be greater than zero")]
+    ZeroDuration,
+    #[error("Math overflow")]
+    MathOverflow,
+    #[error("Insufficient stream balance")]
+    InsufficientStreamBalance,
+    #[error("Cancellation not allowed by the stream policy")]
+    CancellationNotAllowed,
+    #[error("Stream still owes the payee")]
+    StreamNotSettled,
+    #[error("Stream is completed")]
+    StreamCompleted,
+    #[error("Stream is terminated")]
+    StreamTerminated,
+    #[error("Invalid clock timestamp")]
+    InvalidTimestamp,
+    #[error("Stream cannot start in the past")]
+    InvalidStartTime,
+    #[error("Cliff must be within the stream")]
+    InvalidCliff,
+    #[error("Invalid vesting curve")]
+    InvalidVestingCurve,
+    #[error("Stream is paused")]
+    StreamPaused,
+    #[error("Stream is not paused")]
+    StreamNotPaused,
+    /// The signer is neither the payer nor the pause authority of the stream
+    #[error("Signer can't pause or resume the stream")]
+    InvalidPauseAuthority,
+    #[error("Stream can't be topped up")]
+    InvalidTopUp,
+    #[error("Stream payee can't be transferred")]
+    NotTransferable,
+    #[error("Nothing to withdraw")]
+    NothingToWithdraw,
+    /// The crank tip is more than `MAX_CRANK_TIP_BPS` of the stream amount
+    #[error("Crank tip too large")]
+    InvalidCrankTip,
+    #[error("Stream was pushed too recently")]
+    PushTooSoon,
+    #[error("Invalid split stream recipients")]
+    InvalidRecipients,
+    /// The payer can't cover the amount and rent of the stream and keep its own rent exemption
+    #[error("Payer can't fund the stream")]
+    InsufficientFunds,
+    /// More streams than one `CreateBatch` can create within the compute budget
+    #[error("Batch has too many streams")]
+    BatchTooLarge,
+    #[error("Invalid recurring stream terms")]
+    InvalidRecurrence,
+    #[error("Stream is insolvent")]
+    StreamInsolvent,
+    #[error("Stream account has an older layout and needs to be migrated")]
+    StreamNeedsMigration,
+    #[error("Stream account has an unknown layout version")]
+    UnknownStreamVersion,
+    #[error("Stream can't change to the requested status")]
+    InvalidStatusTransition,
 }
 
 impl From<PaystreamError> for ProgramError {
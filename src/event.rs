@@ -0,0 +1,191 @@
+use borsh::{BorshDeserialize, BorshSerialize};
+use solana_program::{msg, pubkey::Pubkey};
+use thiserror::Error;
+
+use crate::state::{StreamAccount, StreamStatus};
+
+/// Version of the event encoding, logged ahead of every event
+pub const EVENT_VERSION: u8 = 1;
+
+/// Starts every event log line, followed by the base64 of the version and the Borsh encoded event
+pub const EVENT_LOG_PREFIX: &str = "PAYSTREAM_EVENT ";
+
+/// Prefix the runtime puts in front of every `msg!`
+const PROGRAM_LOG_PREFIX: &str = "Program log: ";
+
+/// Why a log line carrying an event couldn't be decoded. Only raised off-chain, so it isn't
+/// part of the error codes of the program.
+#[derive(Error, Debug, Copy, Clone, PartialEq)]
+pub enum EventError {
+    #[error("Event log isn't valid base64")]
+    InvalidBase64,
+    #[error("Unknown event version {0}")]
+    UnknownVersion(u8),
+    #[error("Malformed event data")]
+    InvalidData,
+}
+
+/// State changes of a stream, logged by the program for indexers. See `decode_logs` to read them
+/// back from the logs of a transaction.
+#[derive(Debug, Clone, PartialEq, BorshSerialize, BorshDeserialize)]
+pub enum PaystreamEvent {
+    /// A stream was created, `mint` is `Pubkey::default()` for lamport streams
+    StreamCreated {
+        stream: Pubkey,
+        payer: Pubkey,
+        payee: Pubkey,
+        mint: Pubkey,
+        amount: u64,
+        start_time: u64,
+        duration: u64,
+    },
+    /// `amount` was paid out to `payee`, a recipient for split streams, into `destination`, its
+    /// own account or any other it named, `crank_tip` of it to whoever pushed the withdrawal.
+    /// Withdrawals that pay out nothing aren't reported.
+    Withdrawn {
+        stream: Pubkey,
+        payee: Pubkey,
+        destination: Pubkey,
+        amount: u64,
+        crank_tip: u64,
+    },
+    /// The stream was cancelled, paying out what vested and refunding the rest to the payer
+    Cancelled {
+        stream: Pubkey,
+        paid_out: u64,
+        refunded: u64,
+    },
+    /// Everything was paid out after the end of the stream
+    Completed { stream: Pubkey },
+    Paused { stream: Pubkey, paused_at: u64 },
+    /// `paused_duration` is the total time spent paused so far
+    Resumed { stream: Pubkey, paused_duration: u64 },
+    /// `amount` was deposited, bringing the stream to `total_amount` over `duration`
+    ToppedUp {
+        stream: Pubkey,
+        amount: u64,
+        total_amount: u64,
+        duration: u64,
+    },
+    /// A recurring stream ran dry
+    Insolvent { stream: Pubkey },
+    /// An insolvent recurring stream was refilled
+    Solvent { stream: Pubkey },
+    PayeeTransferred {
+        stream: Pubkey,
+        old_payee: Pubkey,
+        new_payee: Pubkey,
+    },
+    Closed { stream: Pubkey },
+    /// A stream was upgraded from layout version `from_version`, `stream` is where it lives now
+    Migrated { stream: Pubkey, from_version: u8 },
+    /// Stream `index` of a `CreateBatch` was created, logged after its `StreamCreated`
+    BatchStreamCreated { index: u32, stream: Pubkey },
+    /// Stream `index` of a `CreateBatch` was skipped, `error` is the code of the `ProgramError`
+    /// it failed with, see `ProgramError::from(u64)`
+    BatchStreamFailed {
+        index: u32,
+        stream: Pubkey,
+        error: u64,
+    },
+}
+
+impl PaystreamEvent {
+    /// Log the event as a single line, see `EVENT_LOG_PREFIX`
+    pub fn emit(&self) {
+        msg!("{}{}", EVENT_LOG_PREFIX, base64::encode(self.pack()));
+    }
+
+    /// The version followed by the Borsh encoded event
+    pub fn pack(&self) -> Vec<u8> {
+        let mut data = vec![EVENT_VERSION];
+        data.extend(self.try_to_vec().unwrap());
+        data
+    }
+
+    /// Decode an event packed by `pack`, rejecting other versions and trailing bytes
+    pub fn unpack(data: &[u8]) -> Result<Self, EventError> {
+        match data.split_first() {
+            Some((&EVENT_VERSION, event)) => {
+                Self::try_from_slice(event).map_err(|_| EventError::InvalidData)
+            }
+            Some((&version, _)) => Err(EventError::UnknownVersion(version)),
+            None => Err(EventError::InvalidData),
+        }
+    }
+
+    pub fn stream_created(stream: Pubkey, stream_data: &StreamAccount) -> Self {
+        Self::StreamCreated {
+            stream,
+            payer: stream_data.payer_pubkey,
+            payee: stream_data.payee_pubkey,
+            mint: stream_data.mint_pubkey,
+            amount: stream_data.amount_in_lamports,
+            start_time: stream_data.start_time,
+            duration: stream_data.duration,
+        }
+    }
+
+    /// The event for a change of status that happened along the way, like the final withdrawal
+    /// completing the stream
+    pub fn status_changed(stream: Pubkey, from: StreamStatus, to: StreamStatus) -> Option<Self> {
+        match (from, to) {
+            (from, to) if from == to => None,
+            (_, StreamStatus::Completed) => Some(Self::Completed { stream }),
+            (_, StreamStatus::Insolvent) => Some(Self::Insolvent { stream }),
+            (StreamStatus::Insolvent, StreamStatus::Active) => Some(Self::Solvent { stream }),
+            _ => None,
+        }
+    }
+}
+
+/// Decode the event of a single log line, `None` if the line isn't one. Takes the line with or
+/// without the `Program log: ` prefix of the runtime.
+pub fn decode_log(line: &str) -> Option<Result<PaystreamEvent, EventError>> {
+    let line = line.strip_prefix(PROGRAM_LOG_PREFIX).unwrap_or(line);
+    let encoded = line.strip_prefix(EVENT_LOG_PREFIX)?;
+
+    Some(
+        base64::decode(encoded)
+            .map_err(|_| EventError::InvalidBase64)
+            .and_then(|data| PaystreamEvent::unpack(&data)),
+    )
+}
+
+/// Decode the events in the logs of a transaction, in order. Only lines logged by `program_id`
+/// itself count, so other programs it calls can't fake events.
+pub fn decode_logs<S: AsRef<str>>(
+    program_id: &Pubkey,
+    logs: &[S],
+) -> Result<Vec<PaystreamEvent>, EventError> {
+    let program_id = program_id.to_string();
+    let mut invoked = vec![];
+    let mut events = vec![];
+
+    for line in logs {
+        let line = line.as_ref();
+        // Follow `Program <id> invoke [<depth>]` and `Program <id> success` or `failed: ...`
+        if let Some(rest) = line.strip_prefix("Program ") {
+            let mut words = rest.split(' ');
+            match (words.next(), words.next()) {
+                (Some(id), Some("invoke")) => {
+                    invoked.push(id);
+                    continue;
+                }
+                (Some(_), Some("success")) | (Some(_), Some("failed:")) => {
+                    invoked.pop();
+                    continue;
+                }
+                _ => {}
+            }
+        }
+
+        if invoked.last() == Some(&program_id.as_str()) {
+            if let Some(event) = decode_log(line) {
+                events.push(event?);
+            }
+        }
+    }
+
+    Ok(events)
+}
@@ -1,75 +1,279 @@
-use solana_program::{program_error::ProgramError, pubkey::Pubkey, instruction::Instruction};
-use std::convert::TryInto;
+use solana_program::{program_error::ProgramError, pubkey::Pubkey, instruction::Instruction, message::Message};
 use solana_program::instruction::AccountMeta;
 use borsh::{BorshDeserialize, BorshSerialize};
-use solana_program::sysvar;
+use solana_program::{system_program, sysvar};
 
-#[derive(Debug, BorshSerialize, BorshDeserialize, )]
+use crate::{
+    find_stream_address, find_vault_authority_address,
+    state::{CancellationPolicy, Recipient, Recurrence, ScheduleMode, TopUpMode, VestingCurve},
+};
+
+/// Largest transaction the cluster accepts, signatures included
+pub const MAX_TRANSACTION_SIZE: usize = 1232;
+
+/// Compute units a transaction gets without asking for more
+pub const MAX_COMPUTE_UNITS: u64 = 200_000;
+
+/// Compute units a `CreateBatch` spends outside of its streams
+pub const BATCH_BASE_COMPUTE_UNITS: u64 = 10_000;
+
+/// Compute units a `CreateBatch` spends on each stream, with headroom. Deriving the address
+/// costs 1500 for each bump seed tried, creating the account through the system program a
+/// little over 1000, and checking, storing and reporting the stream in two events a few thousand
+/// more, around 12k in all. Twice that leaves room for addresses that take more bump seeds.
+pub const BATCH_STREAM_COMPUTE_UNITS: u64 = 25_000;
+
+/// Most streams `create_batches` puts in one `CreateBatch`, to stay within `MAX_COMPUTE_UNITS`
+pub const MAX_BATCH_STREAMS: usize =
+    ((MAX_COMPUTE_UNITS - BATCH_BASE_COMPUTE_UNITS) / BATCH_STREAM_COMPUTE_UNITS) as usize;
+
+/// Terms of a stream, as given to `Create` and `CreateTokenStream`, or shared by a `CreateBatch`
+///
+/// The stream starts at `start_time`, or now if not given, and runs for `duration` slots or
+/// seconds, depending on `schedule_mode`, vesting along `vesting_curve`. Nothing can be withdrawn
+/// before the optional `cliff_time`, at which point everything vested so far unlocks at once.
+/// Besides the payer, the optional `pause_authority` may pause and resume the stream.
+/// The payee can only hand the stream over to someone else if it is `transferable`.
+/// Whoever pushes a withdrawal to the payee gets `crank_tip` out of it, which can be at most
+/// `MAX_CRANK_TIP_BPS` of the amount.
+/// With `recipients` the funds are split among them by weight instead of going to the payee,
+/// and each recipient withdraws its own share.
+/// With a `recurrence` the stream renews every `duration` for as long as it is funded, with
+/// `amount` as the initial deposit. It turns insolvent when it runs dry until refilled with
+/// `TopUp`.
+/// The optional `authority` may cancel, pause, resume and top up the stream on behalf of the
+/// payer. It can be a keypair or the PDA of another program, such as a multisig, signing
+/// through CPI.
+#[derive(Debug, Clone, PartialEq, BorshSerialize, BorshDeserialize, Default)]
+pub struct StreamTerms {
+    pub payee_pubkey: Pubkey,
+    pub payer_pubkey: Pubkey,
+    /// In lamports, or in base units of the mint for token streams
+    pub amount: u64,
+    pub duration: u64,
+    pub seed: u64,
+    pub cancellation_policy: CancellationPolicy,
+    pub schedule_mode: ScheduleMode,
+    pub start_time: Option<u64>,
+    pub cliff_time: Option<u64>,
+    pub vesting_curve: VestingCurve,
+    pub pause_authority: Option<Pubkey>,
+    pub transferable: bool,
+    pub crank_tip: u64,
+    pub recipients: Vec<Recipient>,
+    pub recurrence: Option<Recurrence>,
+    pub authority: Option<Pubkey>,
+}
+
+/// Terms of one stream in a `CreateBatch`, taking the place of the same terms shared by the batch
+#[derive(Debug, Clone, PartialEq, BorshSerialize, BorshDeserialize)]
+pub struct BatchStream {
+    pub payee_pubkey: Pubkey,
+    pub amount: u64,
+    pub duration: u64,
+    pub seed: u64,
+    pub start_time: Option<u64>,
+    pub cliff_time: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, BorshSerialize, BorshDeserialize)]
 pub enum PaystreamInstruction {
-    /// Initialize the stream payment
+    /// Initialize the stream payment on `terms`
+    ///
+    /// The stream account is created by the program at the PDA derived from payer, payee and
+    /// seed (see `find_stream_address`) and funded with rent plus the amount by the payer.
     ///
     /// Accounts expected:
-    /// 0. `[writable]` The stream account created to manage state across 2 parties; owned by program id.
-    /// 1. `[]` Sysvar Rent Account to validate rent exemption (SYSVAR_RENT_PUBKEY)
-    /// 2. `[]` Clock
-    Create {
-        payee_pubkey: Pubkey,
-        payer_pubkey: Pubkey,
-        amount: u64,
-        duration_in_slots: u64,
-    },
+    /// 0. `[writable]` The stream account (PDA) created to manage state across 2 parties.
+    /// 1. `[writable, signer]` Payer account (keypair), must match `payer_pubkey`
+    /// 2. `[]` Sysvar Rent Account to calculate rent exemption (SYSVAR_RENT_PUBKEY)
+    /// 3. `[]` Clock
+    /// 4. `[]` System program
+    Create { terms: StreamTerms },
 
-    /// Withdraw amount from stream
+    /// Withdraw amount from stream, or from the own share for recipients of a split stream
     ///
     /// Accounts expected:
     /// 0. `[writable]` The stream account created to manage state across 2 parties; owned by program id.
-    /// 1. `[signer]` Payee account (keypair)
+    /// 1. `[signer]` Payee or recipient account (keypair)
+    /// 2. `[]` Clock
+    /// 3. `[writable]` Optional destination account to pay out to, the payee account if omitted
+    ///
+    /// Token streams instead expect:
+    /// 3. `[writable]` Vault token account of the stream
+    /// 4. `[writable]` Token account to pay out to, of the payee or any other owner
+    /// 5. `[]` Vault authority (PDA)
+    /// 6. `[]` Token program
     Withdrawal { amount: u64 },
 
     /// Cancel stream payment
+    ///
+    /// Pays the payee everything vested up to now and refunds the unvested rest to the payer.
+    /// Payer or payee signs, as allowed by the cancellation policy of the stream. The authority
+    /// of the stream may sign instead of the payer. On split streams one of the recipients
+    /// signs for the payee side, in place of the payee account.
+    ///
     /// Accounts expected:
     /// 0. `[writable]` The stream account created to manage state across 2 parties; owned by program id.
-    /// 1. `[signer]` Payee account (keypair)
-    /// 2. `[]` Payer (Owner) account (public key)
+    /// 1. `[writable, signer?]` Payee account, or the recipient cancelling a split stream
+    /// 2. `[writable, signer?]` Payer (Owner) account
+    /// 3. `[]` Clock
+    ///
+    /// Token streams additionally expect:
+    /// 4. `[writable]` Vault token account of the stream
+    /// 5. `[writable]` Payee token account to pay out to, unused by split streams
+    /// 6. `[writable]` Payer token account to refund
+    /// 7. `[]` Vault authority (PDA)
+    /// 8. `[]` Token program
+    ///
+    /// Split streams pay each recipient their share, so they also expect the account, or token
+    /// account, of every recipient in order after all of the above.
+    ///
+    /// When the authority signs for the payer, it comes right after all of the above:
+    /// `[signer]` Authority of the stream
     Cancel {},
+
+    /// Initialize a stream payment of SPL tokens on `terms`, escrowing the amount in a vault
+    ///
+    /// The stream account is created by the program at the PDA derived from payer, payee and
+    /// seed, the payer funds its rent.
+    ///
+    /// Accounts expected:
+    /// 0. `[writable]` The stream account (PDA) created to manage state across 2 parties.
+    /// 1. `[writable]` Vault token account for the mint, owned by the vault authority PDA
+    /// 2. `[writable]` Payer token account to fund the stream from
+    /// 3. `[writable, signer]` Payer account (keypair), must match `payer_pubkey` and own the payer token account
+    /// 4. `[]` Mint of the streamed token
+    /// 5. `[]` Sysvar Rent Account to calculate rent exemption (SYSVAR_RENT_PUBKEY)
+    /// 6. `[]` Clock
+    /// 7. `[]` Token program
+    /// 8. `[]` System program
+    CreateTokenStream { terms: StreamTerms },
+
+    /// Close a settled stream, zeroing its data and returning all its lamports, rent included
+    ///
+    /// Accounts expected:
+    /// 0. `[writable]` The stream account created to manage state across 2 parties; owned by program id.
+    /// 1. `[signer]` Payer (Owner) account (keypair)
+    /// 2. `[writable]` Recipient of the remaining lamports, usually the payer
+    ///
+    /// Token streams additionally expect:
+    /// 3. `[writable]` Vault token account of the stream, closed as well
+    /// 4. `[]` Vault authority (PDA)
+    /// 5. `[]` Token program
+    /// 6. `[writable]` Payer token account, swept whatever is left in the vault
+    Close {},
+
+    /// Pause a stream, stopping vesting until it is resumed. The payee can still withdraw
+    /// what vested before the pause.
+    ///
+    /// Accounts expected:
+    /// 0. `[writable]` The stream account created to manage state across 2 parties; owned by program id.
+    /// 1. `[signer]` Payer, authority or pause authority of the stream
+    /// 2. `[]` Clock
+    Pause {},
+
+    /// Resume a paused stream, the time spent paused doesn't count toward vesting
+    ///
+    /// Accounts expected:
+    /// 0. `[writable]` The stream account created to manage state across 2 parties; owned by program id.
+    /// 1. `[signer]` Payer, authority or pause authority of the stream
+    /// 2. `[]` Clock
+    Resume {},
+
+    /// Deposit `amount` more into an active stream, keeping what vested so far. `mode` decides
+    /// whether the stream runs longer at the same rate or vests faster until the same end.
+    /// Only linear streams can be topped up. Recurring streams, insolvent ones included, are
+    /// refilled instead, whatever the `mode`.
+    ///
+    /// Accounts expected:
+    /// 0. `[writable]` The stream account created to manage state across 2 parties; owned by program id.
+    /// 1. `[writable, signer]` Payer (Owner) or authority account, funding the top-up
+    /// 2. `[]` Clock
+    /// 3. `[]` System program
+    ///
+    /// Token streams expect instead of the system program:
+    /// 3. `[writable]` Token account of the signer to fund the top-up from
+    /// 4. `[writable]` Vault token account of the stream
+    /// 5. `[]` Token program
+    TopUp { amount: u64, mode: TopUpMode },
+
+    /// Hand the stream over to `new_payee`, who is paid from then on, including anything vested
+    /// but not yet withdrawn. Only allowed on streams created as transferable. Recipients of a
+    /// split stream hand over their own share instead, its payee has nothing to hand over. The
+    /// stream keeps its address, which is still derived from the original payee.
+    ///
+    /// Accounts expected:
+    /// 0. `[writable]` The stream account created to manage state across 2 parties; owned by program id.
+    /// 1. `[signer]` Current payee account (keypair), or recipient of a split stream
+    TransferPayee { new_payee: Pubkey },
+
+    /// Pay everything vested so far to the payee of the stream, submitted by anyone. The caller
+    /// gets the crank tip of the stream out of the payout, which may be at most
+    /// `MAX_CRANK_TIP_BPS` of it. Pushes are at least `MIN_PUSH_INTERVAL_BPS` of the duration
+    /// apart.
+    ///
+    /// Accounts expected:
+    /// 0. `[writable]` The stream account created to manage state across 2 parties; owned by program id.
+    /// 1. `[writable, signer]` Caller account (keypair), paid the tip
+    /// 2. `[writable]` Payee account of the stream
+    /// 3. `[]` Clock
+    ///
+    /// Token streams additionally expect:
+    /// 4. `[writable]` Vault token account of the stream
+    /// 5. `[writable]` Payee token account to pay out to
+    /// 6. `[]` Vault authority (PDA)
+    /// 7. `[]` Token program
+    /// 8. `[writable]` Caller token account paid the tip, if there is a tip
+    PushWithdrawal {},
+
+    /// Create a lamport stream for each of `streams` funded by the same payer, on `terms` with
+    /// the payee, amount, duration, seed, start and cliff of each stream in place of those of
+    /// `terms`. Streams that can't be created, the payer running out of lamports
+    /// included, are skipped. Every stream emits a `BatchStreamCreated` or `BatchStreamFailed`
+    /// event. Batches of more than `MAX_BATCH_STREAMS` streams fail as a whole. See
+    /// `create_batches` to pack a payroll run into as few transactions as possible.
+    ///
+    /// Accounts expected:
+    /// 0. `[writable, signer]` Payer account (keypair), must match `payer_pubkey`
+    /// 1. `[]` Sysvar Rent Account to calculate rent exemption (SYSVAR_RENT_PUBKEY)
+    /// 2. `[]` Clock
+    /// 3. `[]` System program
+    /// 4. `[writable]` The stream account (PDA) of each of `streams`, in order
+    CreateBatch {
+        terms: StreamTerms,
+        streams: Vec<BatchStream>,
+    },
+
+    /// Upgrade a stream account of an older layout to the current one, see `STREAM_VERSION`.
+    /// Anyone may migrate a stream, the payee as well as the payer. Streams that fit their
+    /// account are rewritten in place. Those that outgrow it move into a new account at the PDA
+    /// derived from payer, payee and `seed`, whose rent the funder pays. The remaining amount
+    /// moves with the stream and the old account is closed to the funder. Unversioned legacy
+    /// streams never fit, so they always move to the PDA chosen by `seed`.
+    ///
+    /// Accounts expected:
+    /// 0. `[writable]` The stream account to migrate; owned by program id.
+    /// 1. `[writable, signer]` Funder account (keypair), signing only needed when the stream moves
+    /// 2. `[]` Sysvar Rent Account to calculate rent exemption (SYSVAR_RENT_PUBKEY)
+    /// 3. `[]` System program
+    /// 4. `[writable]` The new stream account (PDA), only when the stream outgrows its account
+    Migrate { seed: u64 },
 }
 
 impl PaystreamInstruction {
+    /// Decode an instruction, the reverse of `pack`. Short data, unknown tags or values and
+    /// trailing bytes are all rejected.
     pub fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
-        let (tag, rest) = input
-            .split_first()
-            .ok_or(ProgramError::InvalidInstructionData)?;
-
-        Ok(match tag {
-            0 => {
-                let payee_pubkey: Pubkey = Pubkey::new(&rest[..32]);
-                let payer_pubkey: Pubkey = Pubkey::new(&rest[32..64]);
-                let amount: u64 = Self::unpack_u64(&rest, 64)?;
-                let duration_in_slots: u64 = Self::unpack_u64(&rest, 72)?;
-                
-                Self::Create {
-                    payee_pubkey,
-                    payer_pubkey,
-                    amount,
-                    duration_in_slots,
-                }
-            }
-            1 => {
-                let amount: u64 = Self::unpack_u64(&rest, 0)?;
-                Self::Withdrawal { amount }
-            }
-            2 => Self::Cancel {},
-            _ => return Err(ProgramError::InvalidInstructionData),
-        })
+        Self::try_from_slice(input).map_err(|_| ProgramError::InvalidInstructionData)
     }
 
-    fn unpack_u64(input: &[u8], start: usize) -> Result<u64, ProgramError> {
-        let value = input
-            .get(start..8 + start)
-            .and_then(|slice| slice.try_into().ok())
-            .map(u64::from_le_bytes)
-            .ok_or(ProgramError::InvalidInstructionData)?;
-        Ok(value)
+    /// Encode the instruction with Borsh, the only encoding the program accepts: a one byte tag
+    /// with the index of the variant, followed by its fields in order. Integers are little
+    /// endian, `Option` is a 0 or 1 byte followed by the value, `Vec` a `u32` length followed
+    /// by the items and nested enums again a one byte tag followed by their fields.
+    pub fn pack(&self) -> Vec<u8> {
+        self.try_to_vec().unwrap()
     }
 }
 
@@ -78,12 +282,15 @@ pub fn create(
     program_id: Pubkey,
     instruction_data: PaystreamInstruction,
     stream_account_key: Pubkey,
+    payer_account_key: Pubkey,
 ) -> Result<Instruction, ProgramError> {
-    let data = instruction_data.try_to_vec().unwrap();
+    let data = instruction_data.pack();
     let accounts = vec![
-        AccountMeta::new(stream_account_key, true),
+        AccountMeta::new(stream_account_key, false),
+        AccountMeta::new(payer_account_key, true),
         AccountMeta::new_readonly(sysvar::rent::id(), false),
-        AccountMeta::new(sysvar::clock::id(), false),
+        AccountMeta::new_readonly(sysvar::clock::id(), false),
+        AccountMeta::new_readonly(system_program::id(), false),
     ];
 
     Ok(Instruction {
@@ -93,18 +300,24 @@ pub fn create(
     })
 }
 
+/// Withdrawal instruction, paying out to `destination_account_key` if given and to the payee
+/// otherwise
 pub fn withdrawal(
     program_id: Pubkey,
     instruction_data: PaystreamInstruction,
     stream_account_key: Pubkey,
     payee_account_key: Pubkey,
+    destination_account_key: Option<Pubkey>,
 ) -> Result<Instruction, ProgramError> {
-    let data = instruction_data.try_to_vec().unwrap();
-    let accounts = vec![
-        AccountMeta::new(stream_account_key, true),
+    let data = instruction_data.pack();
+    let mut accounts = vec![
+        AccountMeta::new(stream_account_key, false),
         AccountMeta::new(payee_account_key, true),
         AccountMeta::new(sysvar::clock::id(), false),
     ];
+    if let Some(destination_account_key) = destination_account_key {
+        accounts.push(AccountMeta::new(destination_account_key, false));
+    }
 
     Ok(Instruction{
         program_id,
@@ -119,12 +332,294 @@ pub fn cancel(
     stream_account_key: Pubkey,
     payee_account_key: Pubkey,
     payer_account_key: Pubkey,
+    signer_key: Pubkey,
+) -> Result<Instruction, ProgramError> {
+    cancel_with_accounts(
+        program_id,
+        instruction_data,
+        stream_account_key,
+        payee_account_key,
+        payer_account_key,
+        signer_key,
+        vec![],
+    )
+}
+
+/// Cancel instruction for a split stream, paying out to `recipient_account_keys`. A recipient
+/// cancelling passes its own key as `payee_account_key`.
+pub fn cancel_split(
+    program_id: Pubkey,
+    instruction_data: PaystreamInstruction,
+    stream_account_key: Pubkey,
+    payee_account_key: Pubkey,
+    payer_account_key: Pubkey,
+    signer_key: Pubkey,
+    recipient_account_keys: &[Pubkey],
 ) -> Result<Instruction, ProgramError> {
-    let data = instruction_data.try_to_vec().unwrap();
+    cancel_with_accounts(
+        program_id,
+        instruction_data,
+        stream_account_key,
+        payee_account_key,
+        payer_account_key,
+        signer_key,
+        recipient_account_keys
+            .iter()
+            .map(|key| AccountMeta::new(*key, false))
+            .collect(),
+    )
+}
+
+/// Cancel instruction with the token and recipient accounts of the stream in `extra_accounts`,
+/// followed by the authority when it is the signer
+fn cancel_with_accounts(
+    program_id: Pubkey,
+    instruction_data: PaystreamInstruction,
+    stream_account_key: Pubkey,
+    payee_account_key: Pubkey,
+    payer_account_key: Pubkey,
+    signer_key: Pubkey,
+    extra_accounts: Vec<AccountMeta>,
+) -> Result<Instruction, ProgramError> {
+    let data = instruction_data.pack();
+    let mut accounts = vec![
+        AccountMeta::new(stream_account_key, false),
+        AccountMeta::new(payee_account_key, payee_account_key == signer_key),
+        AccountMeta::new(payer_account_key, payer_account_key == signer_key),
+        AccountMeta::new_readonly(sysvar::clock::id(), false),
+    ];
+    accounts.extend(extra_accounts);
+    // Anyone else signs as the authority of the stream
+    if signer_key != payee_account_key && signer_key != payer_account_key {
+        accounts.push(AccountMeta::new_readonly(signer_key, true));
+    }
+
+    Ok(Instruction{
+        program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Create token stream instruction
+pub fn create_token_stream(
+    program_id: Pubkey,
+    instruction_data: PaystreamInstruction,
+    stream_account_key: Pubkey,
+    vault_account_key: Pubkey,
+    payer_token_account_key: Pubkey,
+    payer_account_key: Pubkey,
+    mint_key: Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let data = instruction_data.pack();
     let accounts = vec![
-        AccountMeta::new(stream_account_key, true),
-        AccountMeta::new(payee_account_key, true),
-        AccountMeta::new(payer_account_key, false),
+        AccountMeta::new(stream_account_key, false),
+        AccountMeta::new(vault_account_key, false),
+        AccountMeta::new(payer_token_account_key, false),
+        AccountMeta::new(payer_account_key, true),
+        AccountMeta::new_readonly(mint_key, false),
+        AccountMeta::new_readonly(sysvar::rent::id(), false),
+        AccountMeta::new_readonly(sysvar::clock::id(), false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+        AccountMeta::new_readonly(system_program::id(), false),
+    ];
+
+    Ok(Instruction {
+        program_id,
+        accounts,
+        data,
+    })
+}
+
+pub fn withdrawal_token(
+    program_id: Pubkey,
+    instruction_data: PaystreamInstruction,
+    stream_account_key: Pubkey,
+    payee_account_key: Pubkey,
+    vault_account_key: Pubkey,
+    payee_token_account_key: Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let mut instruction = withdrawal(
+        program_id,
+        instruction_data,
+        stream_account_key,
+        payee_account_key,
+        None,
+    )?;
+    let (vault_authority_key, _) = find_vault_authority_address(&program_id, &stream_account_key);
+    instruction.accounts.extend(vec![
+        AccountMeta::new(vault_account_key, false),
+        AccountMeta::new(payee_token_account_key, false),
+        AccountMeta::new_readonly(vault_authority_key, false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+    ]);
+
+    Ok(instruction)
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn cancel_token(
+    program_id: Pubkey,
+    instruction_data: PaystreamInstruction,
+    stream_account_key: Pubkey,
+    payee_account_key: Pubkey,
+    payer_account_key: Pubkey,
+    signer_key: Pubkey,
+    vault_account_key: Pubkey,
+    payee_token_account_key: Pubkey,
+    payer_token_account_key: Pubkey,
+) -> Result<Instruction, ProgramError> {
+    cancel_with_accounts(
+        program_id,
+        instruction_data,
+        stream_account_key,
+        payee_account_key,
+        payer_account_key,
+        signer_key,
+        cancel_token_accounts(
+            &program_id,
+            &stream_account_key,
+            vault_account_key,
+            payee_token_account_key,
+            payer_token_account_key,
+        ),
+    )
+}
+
+/// Cancel instruction for a split token stream, paying out to `recipient_token_account_keys`
+#[allow(clippy::too_many_arguments)]
+pub fn cancel_split_token(
+    program_id: Pubkey,
+    instruction_data: PaystreamInstruction,
+    stream_account_key: Pubkey,
+    payee_account_key: Pubkey,
+    payer_account_key: Pubkey,
+    signer_key: Pubkey,
+    vault_account_key: Pubkey,
+    payer_token_account_key: Pubkey,
+    recipient_token_account_keys: &[Pubkey],
+) -> Result<Instruction, ProgramError> {
+    // The payee token account isn't used by split streams
+    let mut accounts = cancel_token_accounts(
+        &program_id,
+        &stream_account_key,
+        vault_account_key,
+        payer_token_account_key,
+        payer_token_account_key,
+    );
+    accounts.extend(
+        recipient_token_account_keys
+            .iter()
+            .map(|key| AccountMeta::new(*key, false)),
+    );
+
+    cancel_with_accounts(
+        program_id,
+        instruction_data,
+        stream_account_key,
+        payee_account_key,
+        payer_account_key,
+        signer_key,
+        accounts,
+    )
+}
+
+fn cancel_token_accounts(
+    program_id: &Pubkey,
+    stream_account_key: &Pubkey,
+    vault_account_key: Pubkey,
+    payee_token_account_key: Pubkey,
+    payer_token_account_key: Pubkey,
+) -> Vec<AccountMeta> {
+    let (vault_authority_key, _) = find_vault_authority_address(program_id, stream_account_key);
+    vec![
+        AccountMeta::new(vault_account_key, false),
+        AccountMeta::new(payee_token_account_key, false),
+        AccountMeta::new(payer_token_account_key, false),
+        AccountMeta::new_readonly(vault_authority_key, false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+    ]
+}
+
+pub fn close(
+    program_id: Pubkey,
+    instruction_data: PaystreamInstruction,
+    stream_account_key: Pubkey,
+    payer_account_key: Pubkey,
+    recipient_account_key: Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let data = instruction_data.pack();
+    let accounts = vec![
+        AccountMeta::new(stream_account_key, false),
+        AccountMeta::new_readonly(payer_account_key, true),
+        AccountMeta::new(recipient_account_key, false),
+    ];
+
+    Ok(Instruction{
+        program_id,
+        accounts,
+        data,
+    })
+}
+
+pub fn close_token(
+    program_id: Pubkey,
+    instruction_data: PaystreamInstruction,
+    stream_account_key: Pubkey,
+    payer_account_key: Pubkey,
+    recipient_account_key: Pubkey,
+    vault_account_key: Pubkey,
+    payer_token_account_key: Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let mut instruction = close(
+        program_id,
+        instruction_data,
+        stream_account_key,
+        payer_account_key,
+        recipient_account_key,
+    )?;
+    let (vault_authority_key, _) = find_vault_authority_address(&program_id, &stream_account_key);
+    instruction.accounts.extend(vec![
+        AccountMeta::new(vault_account_key, false),
+        AccountMeta::new_readonly(vault_authority_key, false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+        AccountMeta::new(payer_token_account_key, false),
+    ]);
+
+    Ok(instruction)
+}
+
+pub fn pause(
+    program_id: Pubkey,
+    instruction_data: PaystreamInstruction,
+    stream_account_key: Pubkey,
+    authority_key: Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let data = instruction_data.pack();
+    let accounts = vec![
+        AccountMeta::new(stream_account_key, false),
+        AccountMeta::new_readonly(authority_key, true),
+        AccountMeta::new_readonly(sysvar::clock::id(), false),
+    ];
+
+    Ok(Instruction{
+        program_id,
+        accounts,
+        data,
+    })
+}
+
+pub fn resume(
+    program_id: Pubkey,
+    instruction_data: PaystreamInstruction,
+    stream_account_key: Pubkey,
+    authority_key: Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let data = instruction_data.pack();
+    let accounts = vec![
+        AccountMeta::new(stream_account_key, false),
+        AccountMeta::new_readonly(authority_key, true),
+        AccountMeta::new_readonly(sysvar::clock::id(), false),
     ];
 
     Ok(Instruction{
@@ -132,4 +627,231 @@ pub fn cancel(
         accounts,
         data,
     })
-}
\ No newline at end of file
+}
+
+pub fn top_up(
+    program_id: Pubkey,
+    instruction_data: PaystreamInstruction,
+    stream_account_key: Pubkey,
+    payer_account_key: Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let data = instruction_data.pack();
+    let accounts = vec![
+        AccountMeta::new(stream_account_key, false),
+        AccountMeta::new(payer_account_key, true),
+        AccountMeta::new_readonly(sysvar::clock::id(), false),
+        AccountMeta::new_readonly(system_program::id(), false),
+    ];
+
+    Ok(Instruction{
+        program_id,
+        accounts,
+        data,
+    })
+}
+
+pub fn top_up_token(
+    program_id: Pubkey,
+    instruction_data: PaystreamInstruction,
+    stream_account_key: Pubkey,
+    payer_account_key: Pubkey,
+    payer_token_account_key: Pubkey,
+    vault_account_key: Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let data = instruction_data.pack();
+    let accounts = vec![
+        AccountMeta::new(stream_account_key, false),
+        AccountMeta::new(payer_account_key, true),
+        AccountMeta::new_readonly(sysvar::clock::id(), false),
+        AccountMeta::new(payer_token_account_key, false),
+        AccountMeta::new(vault_account_key, false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+    ];
+
+    Ok(Instruction{
+        program_id,
+        accounts,
+        data,
+    })
+}
+
+pub fn transfer_payee(
+    program_id: Pubkey,
+    instruction_data: PaystreamInstruction,
+    stream_account_key: Pubkey,
+    payee_account_key: Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let data = instruction_data.pack();
+    let accounts = vec![
+        AccountMeta::new(stream_account_key, false),
+        AccountMeta::new_readonly(payee_account_key, true),
+    ];
+
+    Ok(Instruction{
+        program_id,
+        accounts,
+        data,
+    })
+}
+
+pub fn push_withdrawal(
+    program_id: Pubkey,
+    instruction_data: PaystreamInstruction,
+    stream_account_key: Pubkey,
+    caller_account_key: Pubkey,
+    payee_account_key: Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let data = instruction_data.pack();
+    let accounts = vec![
+        AccountMeta::new(stream_account_key, false),
+        AccountMeta::new(caller_account_key, true),
+        AccountMeta::new(payee_account_key, false),
+        AccountMeta::new_readonly(sysvar::clock::id(), false),
+    ];
+
+    Ok(Instruction{
+        program_id,
+        accounts,
+        data,
+    })
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn push_withdrawal_token(
+    program_id: Pubkey,
+    instruction_data: PaystreamInstruction,
+    stream_account_key: Pubkey,
+    caller_account_key: Pubkey,
+    payee_account_key: Pubkey,
+    vault_account_key: Pubkey,
+    payee_token_account_key: Pubkey,
+    caller_token_account_key: Option<Pubkey>,
+) -> Result<Instruction, ProgramError> {
+    let mut instruction = push_withdrawal(
+        program_id,
+        instruction_data,
+        stream_account_key,
+        caller_account_key,
+        payee_account_key,
+    )?;
+    let (vault_authority_key, _) = find_vault_authority_address(&program_id, &stream_account_key);
+    instruction.accounts.extend(vec![
+        AccountMeta::new(vault_account_key, false),
+        AccountMeta::new(payee_token_account_key, false),
+        AccountMeta::new_readonly(vault_authority_key, false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+    ]);
+    if let Some(caller_token_account_key) = caller_token_account_key {
+        instruction
+            .accounts
+            .push(AccountMeta::new(caller_token_account_key, false));
+    }
+
+    Ok(instruction)
+}
+
+/// Create batch instruction, with the stream accounts derived from the payer and each stream
+pub fn create_batch(
+    program_id: Pubkey,
+    instruction_data: PaystreamInstruction,
+) -> Result<Instruction, ProgramError> {
+    let (payer_pubkey, streams) = match &instruction_data {
+        PaystreamInstruction::CreateBatch { terms, streams } => (terms.payer_pubkey, streams),
+        _ => return Err(ProgramError::InvalidInstructionData),
+    };
+    let mut accounts = vec![
+        AccountMeta::new(payer_pubkey, true),
+        AccountMeta::new_readonly(sysvar::rent::id(), false),
+        AccountMeta::new_readonly(sysvar::clock::id(), false),
+        AccountMeta::new_readonly(system_program::id(), false),
+    ];
+    accounts.extend(streams.iter().map(|stream| {
+        let (stream_key, _) =
+            find_stream_address(&program_id, &payer_pubkey, &stream.payee_pubkey, stream.seed);
+        AccountMeta::new(stream_key, false)
+    }));
+    let data = instruction_data.pack();
+
+    Ok(Instruction{
+        program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Split a `CreateBatch` of any size into as few `CreateBatch` instructions as possible, each
+/// small enough to be sent in its own transaction paid for and signed by the payer alone, with
+/// at most `MAX_BATCH_STREAMS` streams to fit the compute budget
+pub fn create_batches(
+    program_id: Pubkey,
+    instruction_data: PaystreamInstruction,
+) -> Result<Vec<Instruction>, ProgramError> {
+    let (terms, mut remaining) = match &instruction_data {
+        PaystreamInstruction::CreateBatch { terms, streams } => (terms, streams.as_slice()),
+        _ => return Err(ProgramError::InvalidInstructionData),
+    };
+    let payer_pubkey = terms.payer_pubkey;
+
+    let batch = |streams: &[BatchStream]| {
+        create_batch(
+            program_id,
+            PaystreamInstruction::CreateBatch {
+                terms: terms.clone(),
+                streams: streams.to_vec(),
+            },
+        )
+    };
+    // One signature, by the payer, followed by the message
+    let fits = |instruction: &Instruction| {
+        1 + 64 + Message::new(std::slice::from_ref(instruction), Some(&payer_pubkey)).serialize().len()
+            <= MAX_TRANSACTION_SIZE
+    };
+
+    let mut instructions = vec![];
+    while !remaining.is_empty() {
+        let mut count = 1;
+        let mut instruction = batch(&remaining[..count])?;
+        if !fits(&instruction) {
+            return Err(ProgramError::InvalidInstructionData);
+        }
+        while count < remaining.len().min(MAX_BATCH_STREAMS) {
+            let larger = batch(&remaining[..count + 1])?;
+            if !fits(&larger) {
+                break;
+            }
+            instruction = larger;
+            count += 1;
+        }
+        instructions.push(instruction);
+        remaining = &remaining[count..];
+    }
+
+    Ok(instructions)
+}
+
+/// Migrate instruction, moving the stream into `new_stream_account_key` if given at the expense of
+/// the funder
+pub fn migrate(
+    program_id: Pubkey,
+    instruction_data: PaystreamInstruction,
+    stream_account_key: Pubkey,
+    funding_account_key: Pubkey,
+    new_stream_account_key: Option<Pubkey>,
+) -> Result<Instruction, ProgramError> {
+    let data = instruction_data.pack();
+    let mut accounts = vec![
+        AccountMeta::new(stream_account_key, false),
+        AccountMeta::new(funding_account_key, true),
+        AccountMeta::new_readonly(sysvar::rent::id(), false),
+        AccountMeta::new_readonly(system_program::id(), false),
+    ];
+    if let Some(new_stream_account_key) = new_stream_account_key {
+        accounts.push(AccountMeta::new(new_stream_account_key, false));
+    }
+
+    Ok(Instruction {
+        program_id,
+        accounts,
+        data,
+    })
+}
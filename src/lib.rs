@@ -1,7 +1,42 @@
 pub mod error;
+pub mod event;
 pub mod instruction;
 pub mod processor;
 pub mod state;
 
 #[cfg(not(feature = "no-entrypoint"))]
 pub mod entrypoint;
+
+use solana_program::pubkey::Pubkey;
+
+/// Seed prefix of stream account PDAs
+pub const STREAM_SEED: &[u8] = b"stream";
+
+/// Seed prefix of the PDA owning the vault token account of a token stream
+pub const VAULT_AUTHORITY_SEED: &[u8] = b"vault";
+
+/// Find the PDA that owns the vault token account for a token stream
+pub fn find_vault_authority_address(program_id: &Pubkey, stream_pubkey: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[VAULT_AUTHORITY_SEED, stream_pubkey.as_ref()], program_id)
+}
+
+/// Find the stream account PDA for a payer, payee and seed, so a stream can be found from its parties
+///
+/// The address is fixed at creation. A stream handed over with `TransferPayee` stays at the
+/// address of the payee it was created for, kept as `original_payee_pubkey` in its state.
+pub fn find_stream_address(
+    program_id: &Pubkey,
+    payer_pubkey: &Pubkey,
+    payee_pubkey: &Pubkey,
+    seed: u64,
+) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[
+            STREAM_SEED,
+            payer_pubkey.as_ref(),
+            payee_pubkey.as_ref(),
+            &seed.to_le_bytes(),
+        ],
+        program_id,
+    )
+}
@@ -1,20 +1,45 @@
-use borsh::{BorshDeserialize, BorshSerialize};
 use solana_program::{
     account_info::{next_account_info, AccountInfo},
     entrypoint::ProgramResult,
     msg,
+    program::{invoke, invoke_signed},
     program_error::ProgramError,
-    program_pack::IsInitialized,
+    program_pack::{IsInitialized, Pack},
     pubkey::Pubkey,
+    system_instruction, system_program,
     sysvar::{clock::Clock, rent::Rent, Sysvar},
 };
 
 use crate::{
     error::PaystreamError,
-    instruction::PaystreamInstruction,
-    state::{StreamAccount, StreamStatus},
+    event::PaystreamEvent,
+    find_stream_address, find_vault_authority_address,
+    instruction::{BatchStream, PaystreamInstruction, StreamTerms, MAX_BATCH_STREAMS},
+    state::{StreamAccount, StreamStatus, TopUpMode, MAX_CRANK_TIP_BPS, STREAM_VERSION},
+    STREAM_SEED, VAULT_AUTHORITY_SEED,
 };
 
+/// Trailing accounts needed to pay out of a token stream
+struct TokenAccounts<'a, 'b> {
+    vault: &'a AccountInfo<'b>,
+    destination: &'a AccountInfo<'b>,
+    authority: &'a AccountInfo<'b>,
+    token_program: &'a AccountInfo<'b>,
+}
+
+impl<'a, 'b> TokenAccounts<'a, 'b> {
+    fn parse<I: Iterator<Item = &'a AccountInfo<'b>>>(
+        accounts_iter: &mut I,
+    ) -> Result<Self, ProgramError> {
+        Ok(Self {
+            vault: next_account_info(accounts_iter)?,
+            destination: next_account_info(accounts_iter)?,
+            authority: next_account_info(accounts_iter)?,
+            token_program: next_account_info(accounts_iter)?,
+        })
+    }
+}
+
 pub struct Processor;
 impl Processor {
     pub fn process(
@@ -25,41 +50,266 @@ impl Processor {
         let instruction = PaystreamInstruction::unpack(instruction_data)?;
 
         match instruction {
-            PaystreamInstruction::Create {
-                payee_pubkey,
-                payer_pubkey,
-                amount,
-                duration_in_slots,
-            } => Self::create_stream(
-                accounts,
-                program_id,
-                payee_pubkey,
-                payer_pubkey,
-                amount,
-                duration_in_slots,
-            ),
+            PaystreamInstruction::Create { terms } => Self::create_stream(accounts, program_id, terms),
             PaystreamInstruction::Withdrawal { amount } => {
                 Self::withdraw(accounts, program_id, amount)
             }
             PaystreamInstruction::Cancel {} => Self::cancel(accounts, program_id),
+            PaystreamInstruction::Close {} => Self::close(accounts, program_id),
+            PaystreamInstruction::Pause {} => Self::pause(accounts, program_id),
+            PaystreamInstruction::Resume {} => Self::resume(accounts, program_id),
+            PaystreamInstruction::TopUp { amount, mode } => {
+                Self::top_up(accounts, program_id, amount, mode)
+            }
+            PaystreamInstruction::PushWithdrawal {} => Self::push_withdrawal(accounts, program_id),
+            PaystreamInstruction::Migrate { seed } => Self::migrate(accounts, program_id, seed),
+            PaystreamInstruction::CreateBatch { terms, streams } => {
+                Self::create_batch(accounts, program_id, terms, streams)
+            }
+            PaystreamInstruction::TransferPayee { new_payee } => {
+                Self::transfer_payee(accounts, program_id, new_payee)
+            }
+            PaystreamInstruction::CreateTokenStream { terms } => {
+                Self::create_token_stream(accounts, program_id, terms)
+            }
         }
     }
 
+    /// Create a lamport stream on `terms`
     fn create_stream(
         accounts: &[AccountInfo],
         program_id: &Pubkey,
-        payee_pubkey: Pubkey,
-        payer_pubkey: Pubkey,
-        amount: u64,
-        duration_in_slots: u64,
+        terms: StreamTerms,
+    ) -> ProgramResult {
+        let accounts_iter = &mut accounts.iter();
+
+        // The stream account to store state of the stream
+        let stream_account = next_account_info(accounts_iter)?;
+        let payer_account = next_account_info(accounts_iter)?;
+        let solana_rent = &Rent::from_account_info(next_account_info(accounts_iter)?)?;
+        let clock = &Clock::from_account_info(next_account_info(accounts_iter)?)?;
+        let system_program = next_account_info(accounts_iter)?;
+
+        let (start_time, cliff_time) = (terms.start_time, terms.cliff_time);
+        let mut stream_data = StreamAccount::from(terms);
+        Self::check_payer_signature(payer_account, &stream_data.payer_pubkey)?;
+        Self::check_terms(&stream_data)?;
+
+        stream_data.transition(StreamStatus::Active)?;
+        stream_data.remaining_lamports = stream_data.amount_in_lamports;
+        let now = stream_data.now(clock)?;
+        stream_data.start_time = start_time.unwrap_or(now);
+        stream_data.cliff_time = cliff_time.unwrap_or(stream_data.start_time);
+        Self::check_schedule(&stream_data, now)?;
+
+        // The stream holds its own rent plus the amount to be streamed
+        let minimum_balance = solana_rent.minimum_balance(stream_data.packed_len()?);
+        msg!("[Paystream] minimum rent {}", minimum_balance);
+
+        Self::create_stream_account(
+            program_id,
+            stream_account,
+            payer_account,
+            system_program,
+            solana_rent,
+            &stream_data,
+            minimum_balance
+                .checked_add(stream_data.amount_in_lamports)
+                .ok_or(PaystreamError::MathOverflow)?,
+        )?;
+
+        stream_data.store(&mut stream_account.data.borrow_mut())?;
+
+        PaystreamEvent::stream_created(*stream_account.key, &stream_data).emit();
+
+        Ok(())
+    }
+
+    /// Create a lamport stream for each of `streams` on the `terms` shared by the batch,
+    /// skipping those that fail. The outcome of each stream is emitted as an event.
+    fn create_batch(
+        accounts: &[AccountInfo],
+        program_id: &Pubkey,
+        terms: StreamTerms,
+        streams: Vec<BatchStream>,
+    ) -> ProgramResult {
+        let accounts_iter = &mut accounts.iter();
+        let payer_account = next_account_info(accounts_iter)?;
+        let rent_info = next_account_info(accounts_iter)?;
+        let clock_info = next_account_info(accounts_iter)?;
+        let system_program = next_account_info(accounts_iter)?;
+
+        // Without the payer signature every stream would fail, so fail the whole batch instead
+        Self::check_payer_signature(payer_account, &terms.payer_pubkey)?;
+
+        if streams.is_empty() {
+            msg!("[Paystream] Batch has no streams");
+            return Err(ProgramError::InvalidInstructionData);
+        }
+        if streams.len() > MAX_BATCH_STREAMS {
+            msg!(
+                "[Paystream] Batch has {} streams, at most {} fit the compute budget",
+                streams.len(),
+                MAX_BATCH_STREAMS
+            );
+            return Err(ProgramError::from(PaystreamError::BatchTooLarge));
+        }
+
+        let total = streams.len();
+        let mut created = 0;
+        for (index, stream) in streams.into_iter().enumerate() {
+            let stream_account = next_account_info(accounts_iter)?;
+            let stream_accounts = [
+                stream_account.clone(),
+                payer_account.clone(),
+                rent_info.clone(),
+                clock_info.clone(),
+                system_program.clone(),
+            ];
+            let stream_terms = StreamTerms {
+                payee_pubkey: stream.payee_pubkey,
+                amount: stream.amount,
+                duration: stream.duration,
+                seed: stream.seed,
+                start_time: stream.start_time,
+                cliff_time: stream.cliff_time,
+                ..terms.clone()
+            };
+
+            match Self::create_stream(&stream_accounts, program_id, stream_terms) {
+                Ok(()) => {
+                    created += 1;
+                    PaystreamEvent::BatchStreamCreated {
+                        index: index as u32,
+                        stream: *stream_account.key,
+                    }
+                    .emit();
+                }
+                Err(error) => {
+                    msg!("[Paystream] Batch stream {} failed: {}", index, error);
+                    PaystreamEvent::BatchStreamFailed {
+                        index: index as u32,
+                        stream: *stream_account.key,
+                        error: u64::from(error),
+                    }
+                    .emit();
+                }
+            }
+        }
+
+        msg!("[Paystream] Created {} of {} batch streams", created, total);
+
+        Ok(())
+    }
+
+    /// Create a token stream on `terms`
+    fn create_token_stream(
+        accounts: &[AccountInfo],
+        program_id: &Pubkey,
+        terms: StreamTerms,
     ) -> ProgramResult {
         let accounts_iter = &mut accounts.iter();
 
         // The stream account to store state of the stream
         let stream_account = next_account_info(accounts_iter)?;
+        let vault_account = next_account_info(accounts_iter)?;
+        let payer_token_account = next_account_info(accounts_iter)?;
+        let payer_account = next_account_info(accounts_iter)?;
+        let mint_account = next_account_info(accounts_iter)?;
+        let solana_rent = &Rent::from_account_info(next_account_info(accounts_iter)?)?;
+        let clock = &Clock::from_account_info(next_account_info(accounts_iter)?)?;
+        let token_program = next_account_info(accounts_iter)?;
+        let system_program = next_account_info(accounts_iter)?;
+
+        if *token_program.key != spl_token::id() {
+            msg!("[Paystream] Incorrect token program {}", token_program.key);
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        let (start_time, cliff_time) = (terms.start_time, terms.cliff_time);
+        let mut stream_data = StreamAccount::from(terms);
+        Self::check_payer_signature(payer_account, &stream_data.payer_pubkey)?;
+        Self::check_terms(&stream_data)?;
+
+        if mint_account.owner != &spl_token::id() {
+            msg!("[Paystream] Mint is not owned by the token program");
+            return Err(ProgramError::from(PaystreamError::InvalidMint));
+        }
+
+        // The vault has to be held by our PDA so only the program can move the tokens
+        let (vault_authority_key, _) = find_vault_authority_address(program_id, stream_account.key);
+        if vault_account.owner != &spl_token::id() {
+            msg!("[Paystream] Vault is not owned by the token program");
+            return Err(ProgramError::from(PaystreamError::InvalidVault));
+        }
+
+        let vault = spl_token::state::Account::unpack(&vault_account.data.borrow())?;
+        if vault.owner != vault_authority_key
+            || vault.mint != *mint_account.key
+            || vault.delegate.is_some()
+            || vault.close_authority.is_some()
+        {
+            msg!("[Paystream] Vault is not controlled by the stream");
+            return Err(ProgramError::from(PaystreamError::InvalidVault));
+        }
+
+        stream_data.transition(StreamStatus::Active)?;
+        stream_data.remaining_lamports = stream_data.amount_in_lamports;
+        let now = stream_data.now(clock)?;
+        stream_data.start_time = start_time.unwrap_or(now);
+        stream_data.cliff_time = cliff_time.unwrap_or(stream_data.start_time);
+        Self::check_schedule(&stream_data, now)?;
+        stream_data.mint_pubkey = *mint_account.key;
+        stream_data.vault_pubkey = *vault_account.key;
+
+        // The stream account only needs to cover its own rent, the tokens live in the vault
+        Self::create_stream_account(
+            program_id,
+            stream_account,
+            payer_account,
+            system_program,
+            solana_rent,
+            &stream_data,
+            solana_rent.minimum_balance(stream_data.packed_len()?),
+        )?;
+
+        // Escrow the tokens in the vault
+        let instruction = spl_token::instruction::transfer(
+            token_program.key,
+            payer_token_account.key,
+            vault_account.key,
+            payer_account.key,
+            &[],
+            stream_data.amount_in_lamports,
+        )?;
+        invoke(
+            &instruction,
+            &[
+                payer_token_account.clone(),
+                vault_account.clone(),
+                payer_account.clone(),
+                token_program.clone(),
+            ],
+        )?;
+
+        stream_data.store(&mut stream_account.data.borrow_mut())?;
+
+        PaystreamEvent::stream_created(*stream_account.key, &stream_data).emit();
+
+        Ok(())
+    }
+
+    /// Upgrade the stream to the current layout, moving it into a new account at the PDA for
+    /// `seed` if it doesn't fit its own
+    fn migrate(accounts: &[AccountInfo], program_id: &Pubkey, seed: u64) -> ProgramResult {
+        let accounts_iter = &mut accounts.iter();
+        let stream_account = next_account_info(accounts_iter)?;
+        let funding_account = next_account_info(accounts_iter)?;
+        let solana_rent = &Rent::from_account_info(next_account_info(accounts_iter)?)?;
+        let system_program = next_account_info(accounts_iter)?;
+
         if stream_account.owner != program_id {
-            msg!("[Paystream] Stream account is not owned by program {} != {}",
-                stream_account.owner, program_id);
+            msg!("[Paystream] Stream account is not owned by program");
             return Err(ProgramError::IncorrectProgramId);
         }
 
@@ -68,58 +318,385 @@ impl Processor {
             return Err(ProgramError::InvalidInstructionData);
         }
 
-        // Check the rent on the stream account
-        let solana_rent = &Rent::from_account_info(next_account_info(accounts_iter)?)?;
-        if !solana_rent.is_exempt(stream_account.lamports(), stream_account.data_len()) {
+        let (mut stream_data, version) = StreamAccount::load_any(&stream_account.data.borrow())?;
+
+        if !stream_data.is_initialized() {
+            msg!("[Paystream] Stream is not initialised");
+            return Err(ProgramError::UninitializedAccount);
+        }
+
+        if version == STREAM_VERSION {
+            msg!("[Paystream] Stream already has layout version {}", version);
+            return Ok(());
+        }
+
+        let space = stream_data.packed_len()?;
+        if space <= stream_account.data_len() {
+            stream_data.store(&mut stream_account.data.borrow_mut())?;
+            msg!("[Paystream] Migrated stream from layout version {} in place", version);
+            PaystreamEvent::Migrated {
+                stream: *stream_account.key,
+                from_version: version,
+            }
+            .emit();
+            return Ok(());
+        }
+
+        // The vault authority is derived from the stream address, so token streams can't move
+        if stream_data.is_token_stream() {
+            msg!("[Paystream] Token stream doesn't fit its account");
+            return Err(ProgramError::AccountDataTooSmall);
+        }
+
+        // Anyone may move a stream, paying the rent of the new account and getting that of the old
+        // one back
+        if !funding_account.is_signer {
+            msg!("[Paystream] Funder needs to be signer");
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        // Accounts can't grow, so the stream moves into a new one
+        let new_stream_account = next_account_info(accounts_iter)?;
+        stream_data.seed = seed;
+        Self::create_stream_account(
+            program_id,
+            new_stream_account,
+            funding_account,
+            system_program,
+            solana_rent,
+            &stream_data,
+            solana_rent.minimum_balance(space),
+        )?;
+        stream_data.store(&mut new_stream_account.data.borrow_mut())?;
+
+        Self::transfer_lamports(stream_account, new_stream_account, stream_data.remaining_lamports)?;
+        let lamports = stream_account.lamports();
+        Self::transfer_lamports(stream_account, funding_account, lamports)?;
+        stream_account.data.borrow_mut().fill(0);
+
+        msg!(
+            "[Paystream] Migrated stream from layout version {} to {}",
+            version,
+            new_stream_account.key
+        );
+        PaystreamEvent::Migrated {
+            stream: *new_stream_account.key,
+            from_version: version,
+        }
+        .emit();
+
+        Ok(())
+    }
+
+    fn close(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+        let accounts_iter = &mut accounts.iter();
+        let stream_account = next_account_info(accounts_iter)?;
+        let payer_account = next_account_info(accounts_iter)?;
+        let recipient_account = next_account_info(accounts_iter)?;
+
+        if stream_account.owner != program_id {
+            msg!("[Paystream] Stream account is not owned by program");
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        if !stream_account.is_writable {
+            msg!("[Paystream] Stream account is not writable");
+            return Err(ProgramError::InvalidInstructionData);
+        }
+
+        if !payer_account.is_signer {
+            msg!("[Paystream] Payer needs to be signer");
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let stream_data = StreamAccount::load(&stream_account.data.borrow())?;
+
+        if !stream_data.is_initialized() {
+            msg!("[Paystream] Stream is not initialised");
+            return Err(ProgramError::UninitializedAccount);
+        }
+
+        if stream_data.payer_pubkey != *payer_account.key {
+            msg!("[Paystream] Signer doesn't match payer");
+            return Err(ProgramError::from(PaystreamError::InvalidPayer));
+        }
+
+        if !stream_data.is_settled() {
             msg!(
-                "[Paystream] Not rent exempt. Balance: {}",
-                stream_account.lamports(),
+                "[Paystream] Stream still holds {} for the payee",
+                stream_data.remaining_lamports
             );
-            return Err(ProgramError::AccountNotRentExempt);
+            return Err(ProgramError::from(PaystreamError::StreamNotSettled));
         }
 
-        let clock_info = next_account_info(accounts_iter)?;
-        let clock = &Clock::from_account_info(clock_info)?;
+        if stream_data.is_token_stream() {
+            let vault = next_account_info(accounts_iter)?;
+            let authority = next_account_info(accounts_iter)?;
+            let token_program = next_account_info(accounts_iter)?;
+            let payer_token_account = next_account_info(accounts_iter)?;
+            let token_accounts = TokenAccounts {
+                vault,
+                destination: payer_token_account,
+                authority,
+                token_program,
+            };
+            Self::close_vault(
+                program_id,
+                stream_account.key,
+                &stream_data,
+                &token_accounts,
+                recipient_account,
+            )?;
+        }
 
-        // Check that the stream has the correct balance
-        let minimum_balance = solana_rent.minimum_balance(stream_account.data_len());
-        msg!("[Paystream] minimum rent {}", minimum_balance);
+        // Wipe the state and hand back everything left, including the rent
+        let lamports = stream_account.lamports();
+        Self::transfer_lamports(stream_account, recipient_account, lamports)?;
+        stream_account.data.borrow_mut().fill(0);
+
+        msg!(
+            "[Paystream] Closed stream account, {} returned to {}",
+            lamports,
+            recipient_account.key
+        );
+        PaystreamEvent::Closed {
+            stream: *stream_account.key,
+        }
+        .emit();
+
+        Ok(())
+    }
+
+    fn check_active(stream_data: &StreamAccount) -> ProgramResult {
+        if stream_data.is_complete() {
+            msg!("[Paystream] Stream is completed");
+            return Err(ProgramError::from(PaystreamError::StreamCompleted));
+        }
+
+        if stream_data.is_terminated() {
+            msg!("[Paystream] Stream is terminated");
+            return Err(ProgramError::from(PaystreamError::StreamTerminated));
+        }
+
+        if stream_data.is_paused() {
+            msg!("[Paystream] Stream is paused");
+            return Err(ProgramError::from(PaystreamError::StreamPaused));
+        }
+
+        if stream_data.is_insolvent() {
+            msg!("[Paystream] Stream is insolvent");
+            return Err(ProgramError::from(PaystreamError::StreamInsolvent));
+        }
+
+        if !stream_data.is_active() {
+            msg!("[Paystream] Stream is not active");
+            return Err(ProgramError::from(PaystreamError::NotActive));
+        }
+
+        Ok(())
+    }
+
+    /// Active, paused or insolvent, i.e. the payee can still be paid out
+    fn check_open(stream_data: &StreamAccount) -> ProgramResult {
+        if stream_data.is_paused() || stream_data.is_insolvent() {
+            return Ok(());
+        }
+
+        Self::check_active(stream_data)
+    }
+
+    /// Reject streams that would have nothing to pay out, would vest instantly, can't vest
+    /// along their curve, can't recur or can't be split among their recipients
+    fn check_terms(stream_data: &StreamAccount) -> ProgramResult {
+        if stream_data.amount_in_lamports == 0 {
+            msg!("[Paystream] Stream amount must be greater than zero");
+            return Err(ProgramError::from(PaystreamError::InsufficientAmount));
+        }
 
-        if solana_rent.minimum_balance(stream_account.data_len()) + amount
-            > stream_account.lamports()
+        if stream_data.duration == 0 {
+            msg!("[Paystream] Stream duration must be greater than zero");
+            return Err(ProgramError::from(PaystreamError::ZeroDuration));
+        }
+
+        if let Err(error) = stream_data
+            .vesting_curve
+            .validate(stream_data.amount_in_lamports, stream_data.duration)
         {
+            msg!("[Paystream] Invalid vesting curve {:?}", stream_data.vesting_curve);
+            return Err(ProgramError::from(error));
+        }
+
+        if let Some(recurrence) = stream_data.recurrence {
+            if let Err(error) =
+                recurrence.validate(stream_data.amount_in_lamports, &stream_data.vesting_curve)
+            {
+                msg!("[Paystream] Invalid recurrence {:?}", recurrence);
+                return Err(ProgramError::from(error));
+            }
+        }
+
+        if !stream_data.covers_crank_tip(stream_data.amount_in_lamports) {
             msg!(
-                "[Paystream] Insufficient amount. Balance: {}",
-                stream_account.lamports()
+                "[Paystream] Crank tip {} is more than {} bps of the amount",
+                stream_data.crank_tip,
+                MAX_CRANK_TIP_BPS
             );
-            return Err(ProgramError::from(PaystreamError::InsufficientAmount));
+            return Err(ProgramError::from(PaystreamError::InvalidCrankTip));
         }
 
-        // Initialise the stream with its initial state
-        let mut stream_data = StreamAccount::try_from_slice(*stream_account.data.borrow())?;
+        if let Err(error) = stream_data.validate_recipients() {
+            msg!("[Paystream] Invalid split between {} recipients", stream_data.recipients.len());
+            return Err(ProgramError::from(error));
+        }
 
-        if stream_data.is_initialized() {
-            msg!("[Paystream] Stream already initialised");
+        Ok(())
+    }
 
-            return Err(ProgramError::AccountAlreadyInitialized);
+    /// The stream has to start now or later, with any cliff falling within the stream
+    fn check_schedule(stream_data: &StreamAccount, now: u64) -> ProgramResult {
+        if stream_data.start_time < now {
+            msg!(
+                "[Paystream] Stream start {} is before now {}",
+                stream_data.start_time,
+                now
+            );
+            return Err(ProgramError::from(PaystreamError::InvalidStartTime));
         }
 
-        stream_data.status = StreamStatus::Active as u8;
-        stream_data.payee_pubkey = payee_pubkey;
-        stream_data.payer_pubkey = payer_pubkey;
-        stream_data.amount_in_lamports = amount;
-        stream_data.remaining_lamports = amount;
-        stream_data.start_timestamp_in_slots = clock.slot;
-        stream_data.duration_in_slots = duration_in_slots;
+        if stream_data.cliff_time < stream_data.start_time
+            || stream_data.cliff_time > stream_data.end_time()?
+        {
+            msg!("[Paystream] Cliff {} is outside the stream", stream_data.cliff_time);
+            return Err(ProgramError::from(PaystreamError::InvalidCliff));
+        }
+
+        Ok(())
+    }
 
-        stream_data
-            .serialize(&mut *stream_account.data.borrow_mut())?;
+    /// The payer named in the instruction has to sign, otherwise anyone could open a stream
+    /// in someone else's name
+    fn check_payer_signature(payer_account: &AccountInfo, payer_pubkey: &Pubkey) -> ProgramResult {
+        if !payer_account.is_signer {
+            msg!("[Paystream] Payer needs to be signer");
+            return Err(ProgramError::MissingRequiredSignature);
+        }
 
-        msg!("[Paystream] Created stream account: {:?}", stream_data);
+        if payer_account.key != payer_pubkey {
+            msg!("[Paystream] Signer doesn't match payer");
+            return Err(ProgramError::from(PaystreamError::PayerNotSigner));
+        }
 
         Ok(())
     }
 
+    /// Create the stream account at the PDA derived from the payer, original payee and seed of
+    /// `stream_data`, sized for it and funded with `lamports` by `funding_account`. Lamports sent
+    /// to the address ahead of time count toward `lamports`.
+    fn create_stream_account<'a>(
+        program_id: &Pubkey,
+        stream_account: &AccountInfo<'a>,
+        funding_account: &AccountInfo<'a>,
+        system_program: &AccountInfo<'a>,
+        rent: &Rent,
+        stream_data: &StreamAccount,
+        lamports: u64,
+    ) -> ProgramResult {
+        if !stream_account.is_writable {
+            msg!("[Paystream] Stream account is not writable");
+            return Err(ProgramError::InvalidInstructionData);
+        }
+
+        let (stream_key, bump_seed) = find_stream_address(
+            program_id,
+            &stream_data.payer_pubkey,
+            &stream_data.original_payee_pubkey,
+            stream_data.seed,
+        );
+        if stream_key != *stream_account.key {
+            msg!("[Paystream] Stream account doesn't match derived address {}", stream_key);
+            return Err(ProgramError::InvalidSeeds);
+        }
+
+        if !stream_account.data_is_empty() || *stream_account.owner != system_program::id() {
+            msg!("[Paystream] Stream already initialised");
+            return Err(ProgramError::AccountAlreadyInitialized);
+        }
+
+        if *system_program.key != system_program::id() {
+            msg!("[Paystream] Incorrect system program {}", system_program.key);
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        let seed_bytes = stream_data.seed.to_le_bytes();
+        let signer_seeds: &[&[u8]] = &[
+            STREAM_SEED,
+            stream_data.payer_pubkey.as_ref(),
+            stream_data.original_payee_pubkey.as_ref(),
+            &seed_bytes,
+            &[bump_seed],
+        ];
+        let space = stream_data.packed_len()? as u64;
+
+        // Check the funds up front, the system program failing to move them would abort the whole
+        // transaction rather than just this stream of a batch
+        let current_lamports = stream_account.lamports();
+        let shortfall = lamports.saturating_sub(current_lamports);
+        let funded = funding_account.data_is_empty()
+            && *funding_account.owner == system_program::id()
+            && match funding_account.lamports().checked_sub(shortfall) {
+                Some(0) => true,
+                Some(left) => rent.is_exempt(left, 0),
+                None => false,
+            };
+        if !funded {
+            msg!("[Paystream] Payer can't fund {} lamports and stay rent exempt", shortfall);
+            return Err(ProgramError::from(PaystreamError::InsufficientFunds));
+        }
+
+        if current_lamports == 0 {
+            let instruction = system_instruction::create_account(
+                funding_account.key,
+                stream_account.key,
+                lamports,
+                space,
+                program_id,
+            );
+            return invoke_signed(
+                &instruction,
+                &[
+                    funding_account.clone(),
+                    stream_account.clone(),
+                    system_program.clone(),
+                ],
+                &[signer_seeds],
+            );
+        }
+
+        // The address is known in advance, so anyone can send it lamports first, which makes
+        // `create_account` fail. Fund the rest and claim the account instead.
+        msg!("[Paystream] Stream account already holds {} lamports", current_lamports);
+        if shortfall > 0 {
+            invoke(
+                &system_instruction::transfer(funding_account.key, stream_account.key, shortfall),
+                &[
+                    funding_account.clone(),
+                    stream_account.clone(),
+                    system_program.clone(),
+                ],
+            )?;
+        }
+        invoke_signed(
+            &system_instruction::allocate(stream_account.key, space),
+            &[stream_account.clone(), system_program.clone()],
+            &[signer_seeds],
+        )?;
+        invoke_signed(
+            &system_instruction::assign(stream_account.key, program_id),
+            &[stream_account.clone(), system_program.clone()],
+            &[signer_seeds],
+        )
+    }
+
     fn withdraw(accounts: &[AccountInfo], program_id: &Pubkey, amount: u64) -> ProgramResult {
         let accounts_iter = &mut accounts.iter();
         let stream_account = next_account_info(accounts_iter)?;
@@ -143,31 +720,24 @@ impl Processor {
         }
 
         // Initialise the stream with its initial state
-        let mut stream_data = StreamAccount::try_from_slice(*stream_account.data.borrow())?;
+        let mut stream_data = StreamAccount::load(&stream_account.data.borrow())?;
 
         if !stream_data.is_initialized() {
             msg!("[Paystream] Stream is not initialised");
             return Err(ProgramError::UninitializedAccount);
         }
 
-        if stream_data.payee_pubkey != *payee_account.key {
+        if !stream_data.is_payee(payee_account.key) {
             msg!("[Paystream] Signer doesn't match payee");
             return Err(ProgramError::from(PaystreamError::InvalidPayee));
         }
 
-        if stream_data.status != StreamStatus::Active as u8 {
-            msg!("[Paystream] Stream is not active");
-            return Err(ProgramError::from(PaystreamError::NotActive));
-        }
+        Self::check_open(&stream_data)?;
 
         // Calculate what *can* be withdrawn
-        // TODO Check that this won't panic on 0
-        let lamport_per_second = stream_data.amount_in_lamports / stream_data.duration_in_slots;
-        // How much time has passed
-        let time_passed = clock.slot - stream_data.start_timestamp_in_slots;
-        msg!("[Paystream] {} seconds have passed", time_passed);
-        // TODO Check that this won't overflow
-        let maximum_amount = lamport_per_second * time_passed;
+        let now = stream_data.now(clock)?;
+        let maximum_amount = stream_data.withdrawable_amount_for(payee_account.key, now)?;
+        msg!("[Paystream] {} can be withdrawn", maximum_amount);
         let amount = if amount > maximum_amount {
             maximum_amount
         } else {
@@ -175,12 +745,32 @@ impl Processor {
         };
 
         msg!("[Paystream] Withdrawal of {} requested", amount);
-         
-        stream_data.remaining_lamports -= amount;
-        stream_data.serialize(&mut &mut stream_account.data.borrow_mut()[..])?;
 
-        **stream_account.try_borrow_mut_lamports()? -= amount;
-        **payee_account.try_borrow_mut_lamports()? += amount;
+        let status = stream_data.status;
+        stream_data.record_withdrawal(payee_account.key, amount)?;
+        stream_data.update_solvency(now)?;
+        if stream_data.is_complete() {
+            msg!("[Paystream] Stream completed");
+        }
+        stream_data.store(&mut stream_account.data.borrow_mut())?;
+
+        let destination = if stream_data.is_token_stream() {
+            let token_accounts = TokenAccounts::parse(accounts_iter)?;
+            Self::transfer_from_vault(
+                program_id,
+                stream_account.key,
+                &stream_data,
+                &token_accounts,
+                None,
+                amount,
+            )?;
+            token_accounts.destination.key
+        } else {
+            // The payee may name another account to be paid, otherwise it is paid itself
+            let destination_account = next_account_info(accounts_iter).unwrap_or(payee_account);
+            Self::transfer_lamports(stream_account, destination_account, amount)?;
+            destination_account.key
+        };
         
         // let instruction =
         //     system_instruction::transfer(&stream_account.key, &payee_account.key, amount);
@@ -193,12 +783,132 @@ impl Processor {
         //     ],
         // )?;
         
+        if amount > 0 {
+            PaystreamEvent::Withdrawn {
+                stream: *stream_account.key,
+                payee: *payee_account.key,
+                destination: *destination,
+                amount,
+                crank_tip: 0,
+            }
+            .emit();
+        }
+        Self::emit_status_change(stream_account.key, status, &stream_data);
+
+        Ok(())
+    }
+
+    /// Pay everything vested to the stored payee, or one of the recipients of a split stream, on
+    /// behalf of anyone, who may get the crank tip of the stream out of it
+    fn push_withdrawal(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+        let accounts_iter = &mut accounts.iter();
+        let stream_account = next_account_info(accounts_iter)?;
+        let caller_account = next_account_info(accounts_iter)?;
+        let payee_account = next_account_info(accounts_iter)?;
+        let clock = &Clock::from_account_info(next_account_info(accounts_iter)?)?;
+
+        if stream_account.owner != program_id {
+            msg!("[Paystream] Stream account is not owned by program");
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        if !stream_account.is_writable {
+            msg!("[Paystream] Stream account is not writable");
+            return Err(ProgramError::InvalidInstructionData);
+        }
+
+        if !caller_account.is_signer {
+            msg!("[Paystream] Caller needs to be signer");
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let mut stream_data = StreamAccount::load(&stream_account.data.borrow())?;
+
+        if !stream_data.is_initialized() {
+            msg!("[Paystream] Stream is not initialised");
+            return Err(ProgramError::UninitializedAccount);
+        }
+
+        if !stream_data.is_payee(payee_account.key) {
+            msg!("[Paystream] Payee doesn't match");
+            return Err(ProgramError::from(PaystreamError::InvalidPayee));
+        }
+
+        Self::check_open(&stream_data)?;
+
+        let now = stream_data.now(clock)?;
+        if now < stream_data.next_push_time() {
+            msg!("[Paystream] Stream can't be pushed again before {}", stream_data.next_push_time());
+            return Err(ProgramError::from(PaystreamError::PushTooSoon));
+        }
+
+        // The tip comes out of the payout, so the payout has to be large enough for the tip to
+        // take only a small cut of it
+        let amount = stream_data.withdrawable_amount_for(payee_account.key, now)?;
+        if amount == 0 || !stream_data.covers_crank_tip(amount) {
+            msg!(
+                "[Paystream] {} vested doesn't cover the crank tip {}",
+                amount,
+                stream_data.crank_tip
+            );
+            return Err(ProgramError::from(PaystreamError::NothingToWithdraw));
+        }
+        let tip = stream_data.crank_tip;
+        let payee_amount = amount - tip;
         msg!(
-            "[Paystream] Withdrawal of {} from stream account: {:?}",
-            amount,
-            stream_data
+            "[Paystream] Push withdrawal of {} to payee, {} tip to {}",
+            payee_amount,
+            tip,
+            caller_account.key
         );
 
+        let status = stream_data.status;
+        stream_data.record_withdrawal(payee_account.key, amount)?;
+        stream_data.update_solvency(now)?;
+        stream_data.last_push_time = now;
+        stream_data.store(&mut stream_account.data.borrow_mut())?;
+
+        let destination = if stream_data.is_token_stream() {
+            let to_payee = TokenAccounts::parse(accounts_iter)?;
+            Self::transfer_from_vault(
+                program_id,
+                stream_account.key,
+                &stream_data,
+                &to_payee,
+                Some(payee_account.key),
+                payee_amount,
+            )?;
+            if tip > 0 {
+                let to_caller = TokenAccounts {
+                    destination: next_account_info(accounts_iter)?,
+                    ..to_payee
+                };
+                Self::transfer_from_vault(
+                    program_id,
+                    stream_account.key,
+                    &stream_data,
+                    &to_caller,
+                    None,
+                    tip,
+                )?;
+            }
+            to_payee.destination.key
+        } else {
+            Self::transfer_lamports(stream_account, payee_account, payee_amount)?;
+            Self::transfer_lamports(stream_account, caller_account, tip)?;
+            payee_account.key
+        };
+
+        PaystreamEvent::Withdrawn {
+            stream: *stream_account.key,
+            payee: *payee_account.key,
+            destination: *destination,
+            amount,
+            crank_tip: tip,
+        }
+        .emit();
+        Self::emit_status_change(stream_account.key, status, &stream_data);
+
         Ok(())
     }
 
@@ -207,6 +917,7 @@ impl Processor {
         let stream_account = next_account_info(accounts_iter)?;
         let payee_account = next_account_info(accounts_iter)?;
         let payer_account = next_account_info(accounts_iter)?;
+        let clock = &Clock::from_account_info(next_account_info(accounts_iter)?)?;
         
         if stream_account.owner != program_id {
             msg!("[Paystream] Stream account is not owned by program");
@@ -218,21 +929,38 @@ impl Processor {
             return Err(ProgramError::InvalidInstructionData);
         }
 
-        if !payee_account.is_signer {
-            msg!("[Paystream] Payee needs to be signer");
-            return Err(ProgramError::MissingRequiredSignature);
-        }
-
         // Initialise the stream with its initial state
-        let mut stream_data = StreamAccount::try_from_slice(*stream_account.data.borrow())?;
+        let mut stream_data = StreamAccount::load(&stream_account.data.borrow())?;
 
         if !stream_data.is_initialized() {
             msg!("[Paystream] Stream is not initialised");
             return Err(ProgramError::UninitializedAccount);
         }
 
-        if stream_data.payee_pubkey != *payee_account.key {
-            msg!("[Paystream] Signer doesn't match payee");
+        // The authority signs for the payer right after the token and recipient accounts the
+        // stream expects, see `PaystreamInstruction::Cancel`
+        let mut authority_index = 4;
+        if stream_data.is_token_stream() {
+            authority_index += 5;
+        }
+        if stream_data.is_split() {
+            authority_index += stream_data.recipients.len();
+        }
+        let authority_signed = matches!(
+            accounts.get(authority_index),
+            Some(account) if account.is_signer && stream_data.is_authority(account.key)
+        );
+        let payer_signed = payer_account.is_signer || authority_signed;
+        if !payee_account.is_signer && !payer_signed {
+            msg!("[Paystream] Payee, payer or authority needs to be signer");
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        // Split streams pay their recipients, so any of them signs for the payee side instead of
+        // the payee, who is paid nothing
+        let payee_signed = payee_account.is_signer && stream_data.is_payee(payee_account.key);
+        if stream_data.payee_pubkey != *payee_account.key && !payee_signed {
+            msg!("[Paystream] Payee doesn't match");
             return Err(ProgramError::from(PaystreamError::InvalidPayee));
         }
 
@@ -241,40 +969,592 @@ impl Processor {
             return Err(ProgramError::from(PaystreamError::InvalidPayer));
         }
 
-        if stream_data.status != StreamStatus::Active as u8 {
-            msg!("[Paystream] Stream is not active");
-            return Err(ProgramError::from(PaystreamError::NotActive));
+        if !stream_data
+            .cancellation_policy
+            .allows(payer_signed, payee_signed)
+        {
+            msg!(
+                "[Paystream] Cancellation not allowed by policy {:?}",
+                stream_data.cancellation_policy
+            );
+            return Err(ProgramError::from(PaystreamError::CancellationNotAllowed));
         }
 
-        // Credit amount remaining back to payer
-        // TODO clean up the rental dust
-        msg!("[Paystream] Cancel requested");
-        
-        **stream_account.try_borrow_mut_lamports()? -= stream_data.remaining_lamports;
-        **payer_account.try_borrow_mut_lamports()? += stream_data.remaining_lamports;
+        Self::check_open(&stream_data)?;
 
-        // let instruction =
-        //     system_instruction::transfer(&stream_account.key,
-        //                                     payer_account.key,
-        //                                     stream_data.amount);
-        // invoke(
-        //     &instruction,
-        //     &[
-        //         system_account.clone(),
-        //         payer_account.clone(),
-        //         stream_account.clone(),
-        //     ],
-        // )?;
+        // The payee keeps everything vested up to now, only the unvested rest goes back. Split
+        // streams pay each recipient their own share.
+        let now = stream_data.now(clock)?;
+        let payee_amounts = if stream_data.is_split() {
+            stream_data.owed_to_recipients(now)?
+        } else {
+            vec![stream_data.withdrawable_amount(now)?]
+        };
+        let payee_amount = payee_amounts
+            .iter()
+            .try_fold(0u64, |total, amount| total.checked_add(*amount))
+            .ok_or(PaystreamError::MathOverflow)?;
+        let payer_amount = stream_data
+            .remaining_lamports
+            .checked_sub(payee_amount)
+            .ok_or(PaystreamError::InsufficientStreamBalance)?;
+        msg!(
+            "[Paystream] Cancel requested, {} to payee and {} to payer",
+            payee_amount,
+            payer_amount
+        );
+
+        if stream_data.is_token_stream() {
+            let vault = next_account_info(accounts_iter)?;
+            let payee_token_account = next_account_info(accounts_iter)?;
+            let payer_token_account = next_account_info(accounts_iter)?;
+            let authority = next_account_info(accounts_iter)?;
+            let token_program = next_account_info(accounts_iter)?;
+            let to_payee = TokenAccounts {
+                vault,
+                destination: payee_token_account,
+                authority,
+                token_program,
+            };
+            let to_payer = TokenAccounts {
+                destination: payer_token_account,
+                ..to_payee
+            };
+
+            if stream_data.is_split() {
+                for (recipient, amount) in stream_data.recipients.iter().zip(&payee_amounts) {
+                    let to_recipient = TokenAccounts {
+                        destination: next_account_info(accounts_iter)?,
+                        ..to_payee
+                    };
+                    if *amount > 0 {
+                        Self::transfer_from_vault(
+                            program_id,
+                            stream_account.key,
+                            &stream_data,
+                            &to_recipient,
+                            Some(&recipient.pubkey),
+                            *amount,
+                        )?;
+                    }
+                }
+            } else if payee_amount > 0 {
+                Self::transfer_from_vault(
+                    program_id,
+                    stream_account.key,
+                    &stream_data,
+                    &to_payee,
+                    Some(&stream_data.payee_pubkey),
+                    payee_amount,
+                )?;
+            }
+            if payer_amount > 0 {
+                Self::transfer_from_vault(
+                    program_id,
+                    stream_account.key,
+                    &stream_data,
+                    &to_payer,
+                    Some(&stream_data.payer_pubkey),
+                    payer_amount,
+                )?;
+            }
+        } else {
+            if stream_data.is_split() {
+                for (recipient, amount) in stream_data.recipients.iter().zip(&payee_amounts) {
+                    let recipient_account = next_account_info(accounts_iter)?;
+                    if *recipient_account.key != recipient.pubkey {
+                        msg!("[Paystream] Recipient {} doesn't match", recipient_account.key);
+                        return Err(ProgramError::from(PaystreamError::InvalidPayee));
+                    }
+                    Self::transfer_lamports(stream_account, recipient_account, *amount)?;
+                }
+            } else {
+                Self::transfer_lamports(stream_account, payee_account, payee_amount)?;
+            }
+            Self::transfer_lamports(stream_account, payer_account, payer_amount)?;
+        }
 
+        for (recipient, amount) in stream_data.recipients.iter_mut().zip(&payee_amounts) {
+            recipient.withdrawn_lamports = recipient
+                .withdrawn_lamports
+                .checked_add(*amount)
+                .ok_or(PaystreamError::MathOverflow)?;
+        }
+        stream_data.withdrawn_lamports = stream_data
+            .withdrawn_lamports
+            .checked_add(payee_amount)
+            .ok_or(PaystreamError::MathOverflow)?;
+        stream_data.refunded_lamports = payer_amount;
         stream_data.remaining_lamports = 0;
-        stream_data.status = StreamStatus::Terminated as u8;
-        stream_data.serialize(&mut &mut stream_account.data.borrow_mut()[..])?;
+        stream_data.transition(StreamStatus::Terminated)?;
+        stream_data.store(&mut stream_account.data.borrow_mut())?;
+
+        PaystreamEvent::Cancelled {
+            stream: *stream_account.key,
+            paid_out: payee_amount,
+            refunded: payer_amount,
+        }
+        .emit();
+
+        Ok(())
+    }
+
+    fn pause(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+        let accounts_iter = &mut accounts.iter();
+        let stream_account = next_account_info(accounts_iter)?;
+        let authority_account = next_account_info(accounts_iter)?;
+        let clock = &Clock::from_account_info(next_account_info(accounts_iter)?)?;
+
+        let mut stream_data = Self::load_for_pause(program_id, stream_account, authority_account)?;
+
+        Self::check_active(&stream_data)?;
+
+        stream_data.paused_at = stream_data.now(clock)?;
+        stream_data.transition(StreamStatus::Paused)?;
+        stream_data.store(&mut stream_account.data.borrow_mut())?;
+
+        msg!("[Paystream] Paused stream at {}", stream_data.paused_at);
+        PaystreamEvent::Paused {
+            stream: *stream_account.key,
+            paused_at: stream_data.paused_at,
+        }
+        .emit();
+
+        Ok(())
+    }
+
+    fn resume(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+        let accounts_iter = &mut accounts.iter();
+        let stream_account = next_account_info(accounts_iter)?;
+        let authority_account = next_account_info(accounts_iter)?;
+        let clock = &Clock::from_account_info(next_account_info(accounts_iter)?)?;
+
+        let mut stream_data = Self::load_for_pause(program_id, stream_account, authority_account)?;
+
+        if !stream_data.is_paused() {
+            msg!("[Paystream] Stream is not paused");
+            return Err(ProgramError::from(PaystreamError::StreamNotPaused));
+        }
+
+        // Only time after the start of the stream would have vested, so only that is made up for
+        let paused = stream_data
+            .now(clock)?
+            .saturating_sub(stream_data.paused_at.max(stream_data.start_time));
+        stream_data.paused_duration = stream_data
+            .paused_duration
+            .checked_add(paused)
+            .ok_or(PaystreamError::MathOverflow)?;
+        stream_data.paused_at = 0;
+        stream_data.transition(StreamStatus::Active)?;
+        stream_data.store(&mut stream_account.data.borrow_mut())?;
+
+        msg!(
+            "[Paystream] Resumed stream after {}, paused {} in total",
+            paused,
+            stream_data.paused_duration
+        );
+        PaystreamEvent::Resumed {
+            stream: *stream_account.key,
+            paused_duration: stream_data.paused_duration,
+        }
+        .emit();
+
+        Ok(())
+    }
+
+    fn top_up(
+        accounts: &[AccountInfo],
+        program_id: &Pubkey,
+        amount: u64,
+        mode: TopUpMode,
+    ) -> ProgramResult {
+        let accounts_iter = &mut accounts.iter();
+        let stream_account = next_account_info(accounts_iter)?;
+        let payer_account = next_account_info(accounts_iter)?;
+        let clock = &Clock::from_account_info(next_account_info(accounts_iter)?)?;
+
+        if stream_account.owner != program_id {
+            msg!("[Paystream] Stream account is not owned by program");
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        if !stream_account.is_writable {
+            msg!("[Paystream] Stream account is not writable");
+            return Err(ProgramError::InvalidInstructionData);
+        }
+
+        if !payer_account.is_signer {
+            msg!("[Paystream] Payer needs to be signer");
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let mut stream_data = StreamAccount::load(&stream_account.data.borrow())?;
+
+        if !stream_data.is_initialized() {
+            msg!("[Paystream] Stream is not initialised");
+            return Err(ProgramError::UninitializedAccount);
+        }
+
+        if !stream_data.acts_for_payer(payer_account.key) {
+            msg!("[Paystream] Signer is neither payer nor authority");
+            return Err(ProgramError::from(PaystreamError::InvalidPayer));
+        }
+
+        if amount == 0 {
+            msg!("[Paystream] Top-up amount must be greater than zero");
+            return Err(ProgramError::from(PaystreamError::InsufficientAmount));
+        }
+
+        // Insolvent recurring streams are exactly the ones that need refilling
+        if !stream_data.is_insolvent() {
+            Self::check_active(&stream_data)?;
+        }
+
+        let now = stream_data.now(clock)?;
+        let status = stream_data.status;
+        if let Err(error) = stream_data.top_up(amount, mode, now) {
+            msg!("[Paystream] Can't top up {:?} stream by {}", stream_data.vesting_curve, amount);
+            return Err(ProgramError::from(error));
+        }
+        stream_data.update_solvency(now)?;
+
+        if stream_data.is_token_stream() {
+            let payer_token_account = next_account_info(accounts_iter)?;
+            let vault_account = next_account_info(accounts_iter)?;
+            let token_program = next_account_info(accounts_iter)?;
+
+            if *token_program.key != spl_token::id() {
+                msg!("[Paystream] Incorrect token program {}", token_program.key);
+                return Err(ProgramError::IncorrectProgramId);
+            }
+
+            if *vault_account.key != stream_data.vault_pubkey {
+                msg!("[Paystream] Vault doesn't match the stream");
+                return Err(ProgramError::from(PaystreamError::InvalidVault));
+            }
+
+            let instruction = spl_token::instruction::transfer(
+                token_program.key,
+                payer_token_account.key,
+                vault_account.key,
+                payer_account.key,
+                &[],
+                amount,
+            )?;
+            invoke(
+                &instruction,
+                &[
+                    payer_token_account.clone(),
+                    vault_account.clone(),
+                    payer_account.clone(),
+                    token_program.clone(),
+                ],
+            )?;
+        } else {
+            let system_program = next_account_info(accounts_iter)?;
+
+            if *system_program.key != system_program::id() {
+                msg!("[Paystream] Incorrect system program {}", system_program.key);
+                return Err(ProgramError::IncorrectProgramId);
+            }
+
+            invoke(
+                &system_instruction::transfer(payer_account.key, stream_account.key, amount),
+                &[
+                    payer_account.clone(),
+                    stream_account.clone(),
+                    system_program.clone(),
+                ],
+            )?;
+        }
+
+        stream_data.store(&mut stream_account.data.borrow_mut())?;
+
+        PaystreamEvent::ToppedUp {
+            stream: *stream_account.key,
+            amount,
+            total_amount: stream_data.amount_in_lamports,
+            duration: stream_data.duration,
+        }
+        .emit();
+        Self::emit_status_change(stream_account.key, status, &stream_data);
+
+        Ok(())
+    }
+
+    fn transfer_payee(
+        accounts: &[AccountInfo],
+        program_id: &Pubkey,
+        new_payee: Pubkey,
+    ) -> ProgramResult {
+        let accounts_iter = &mut accounts.iter();
+        let stream_account = next_account_info(accounts_iter)?;
+        let payee_account = next_account_info(accounts_iter)?;
+
+        if stream_account.owner != program_id {
+            msg!("[Paystream] Stream account is not owned by program");
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        if !stream_account.is_writable {
+            msg!("[Paystream] Stream account is not writable");
+            return Err(ProgramError::InvalidInstructionData);
+        }
+
+        if !payee_account.is_signer {
+            msg!("[Paystream] Payee needs to be signer");
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let mut stream_data = StreamAccount::load(&stream_account.data.borrow())?;
+
+        if !stream_data.is_initialized() {
+            msg!("[Paystream] Stream is not initialised");
+            return Err(ProgramError::UninitializedAccount);
+        }
+
+        // Split streams pay their recipients rather than the payee, so only they have anything to
+        // hand over
+        if !stream_data.is_payee(payee_account.key) {
+            msg!("[Paystream] Signer isn't paid by the stream");
+            return Err(ProgramError::from(PaystreamError::InvalidPayee));
+        }
+
+        if !stream_data.transferable {
+            msg!("[Paystream] Stream is not transferable");
+            return Err(ProgramError::from(PaystreamError::NotTransferable));
+        }
+
+        if new_payee == Pubkey::default() {
+            msg!("[Paystream] New payee can't be the default pubkey");
+            return Err(ProgramError::from(PaystreamError::InvalidPayee));
+        }
+
+        Self::check_open(&stream_data)?;
+
+        // Recipients of a split stream hand over their own share, the payee its role
+        match stream_data.recipient_index(payee_account.key) {
+            Some(index) => {
+                if stream_data.recipient_index(&new_payee).is_some() {
+                    msg!("[Paystream] {} is already a recipient", new_payee);
+                    return Err(ProgramError::from(PaystreamError::InvalidRecipients));
+                }
+                stream_data.recipients[index].pubkey = new_payee;
+            }
+            None => stream_data.payee_pubkey = new_payee,
+        }
+        stream_data.store(&mut stream_account.data.borrow_mut())?;
 
         msg!(
-            "[Paystream] Cancelled stream account: {:?}",
-            stream_data
+            "[Paystream] Transferred payee of stream {} from {} to {}",
+            stream_account.key,
+            payee_account.key,
+            new_payee
         );
+        PaystreamEvent::PayeeTransferred {
+            stream: *stream_account.key,
+            old_payee: *payee_account.key,
+            new_payee,
+        }
+        .emit();
 
         Ok(())
     }
-}
\ No newline at end of file
+
+    /// Log the change of status a payout or top-up caused along the way, if any
+    fn emit_status_change(stream_key: &Pubkey, status: StreamStatus, stream_data: &StreamAccount) {
+        if let Some(event) = PaystreamEvent::status_changed(*stream_key, status, stream_data.status) {
+            event.emit();
+        }
+    }
+
+    /// Load the stream for pausing or resuming by `authority_account`
+    fn load_for_pause(
+        program_id: &Pubkey,
+        stream_account: &AccountInfo,
+        authority_account: &AccountInfo,
+    ) -> Result<StreamAccount, ProgramError> {
+        if stream_account.owner != program_id {
+            msg!("[Paystream] Stream account is not owned by program");
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        if !stream_account.is_writable {
+            msg!("[Paystream] Stream account is not writable");
+            return Err(ProgramError::InvalidInstructionData);
+        }
+
+        if !authority_account.is_signer {
+            msg!("[Paystream] Pause authority needs to be signer");
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let stream_data = StreamAccount::load(&stream_account.data.borrow())?;
+
+        if !stream_data.is_initialized() {
+            msg!("[Paystream] Stream is not initialised");
+            return Err(ProgramError::UninitializedAccount);
+        }
+
+        if !stream_data.can_pause(authority_account.key) {
+            msg!("[Paystream] {} can't pause or resume the stream", authority_account.key);
+            return Err(ProgramError::from(PaystreamError::InvalidPauseAuthority));
+        }
+
+        Ok(stream_data)
+    }
+
+    /// Move `amount` lamports out of the program owned stream account
+    fn transfer_lamports(
+        stream_account: &AccountInfo,
+        destination_account: &AccountInfo,
+        amount: u64,
+    ) -> ProgramResult {
+        let stream_lamports = stream_account
+            .lamports()
+            .checked_sub(amount)
+            .ok_or(PaystreamError::InsufficientStreamBalance)?;
+        let destination_lamports = destination_account
+            .lamports()
+            .checked_add(amount)
+            .ok_or(PaystreamError::MathOverflow)?;
+
+        **stream_account.try_borrow_mut_lamports()? = stream_lamports;
+        **destination_account.try_borrow_mut_lamports()? = destination_lamports;
+
+        Ok(())
+    }
+
+    /// Transfer `amount` tokens from the stream vault to the destination token account, signed by
+    /// the vault authority PDA. If `recipient` is given the destination has to be owned by it.
+    fn transfer_from_vault(
+        program_id: &Pubkey,
+        stream_key: &Pubkey,
+        stream_data: &StreamAccount,
+        token_accounts: &TokenAccounts,
+        recipient: Option<&Pubkey>,
+        amount: u64,
+    ) -> ProgramResult {
+        let bump_seed = Self::check_vault(
+            program_id,
+            stream_key,
+            stream_data,
+            token_accounts.vault,
+            token_accounts.authority,
+            token_accounts.token_program,
+        )?;
+
+        if token_accounts.destination.owner != &spl_token::id() {
+            msg!("[Paystream] Destination is not a token account");
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        if let Some(recipient) = recipient {
+            let destination =
+                spl_token::state::Account::unpack(&token_accounts.destination.data.borrow())?;
+            if destination.owner != *recipient {
+                msg!("[Paystream] Destination token account is not owned by {}", recipient);
+                return Err(ProgramError::InvalidAccountData);
+            }
+        }
+
+        let instruction = spl_token::instruction::transfer(
+            token_accounts.token_program.key,
+            token_accounts.vault.key,
+            token_accounts.destination.key,
+            token_accounts.authority.key,
+            &[],
+            amount,
+        )?;
+        invoke_signed(
+            &instruction,
+            &[
+                token_accounts.vault.clone(),
+                token_accounts.destination.clone(),
+                token_accounts.authority.clone(),
+                token_accounts.token_program.clone(),
+            ],
+            &[&[VAULT_AUTHORITY_SEED, stream_key.as_ref(), &[bump_seed]]],
+        )
+    }
+
+    /// Close the vault of a settled token stream to `recipient_account`. The token program only
+    /// closes empty accounts, so tokens sent to the vault on top of the stream are swept to the
+    /// payer token account first.
+    fn close_vault<'a>(
+        program_id: &Pubkey,
+        stream_key: &Pubkey,
+        stream_data: &StreamAccount,
+        token_accounts: &TokenAccounts<'_, 'a>,
+        recipient_account: &AccountInfo<'a>,
+    ) -> ProgramResult {
+        let bump_seed = Self::check_vault(
+            program_id,
+            stream_key,
+            stream_data,
+            token_accounts.vault,
+            token_accounts.authority,
+            token_accounts.token_program,
+        )?;
+
+        let leftover = spl_token::state::Account::unpack(&token_accounts.vault.data.borrow())?.amount;
+        if leftover > 0 {
+            msg!("[Paystream] Sweeping {} left in the vault to the payer", leftover);
+            Self::transfer_from_vault(
+                program_id,
+                stream_key,
+                stream_data,
+                token_accounts,
+                Some(&stream_data.payer_pubkey),
+                leftover,
+            )?;
+        }
+
+        let instruction = spl_token::instruction::close_account(
+            token_accounts.token_program.key,
+            token_accounts.vault.key,
+            recipient_account.key,
+            token_accounts.authority.key,
+            &[],
+        )?;
+        invoke_signed(
+            &instruction,
+            &[
+                token_accounts.vault.clone(),
+                recipient_account.clone(),
+                token_accounts.authority.clone(),
+                token_accounts.token_program.clone(),
+            ],
+            &[&[VAULT_AUTHORITY_SEED, stream_key.as_ref(), &[bump_seed]]],
+        )
+    }
+
+    /// Check the vault accounts passed for a token stream, returning the bump seed of the
+    /// vault authority
+    fn check_vault(
+        program_id: &Pubkey,
+        stream_key: &Pubkey,
+        stream_data: &StreamAccount,
+        vault: &AccountInfo,
+        authority: &AccountInfo,
+        token_program: &AccountInfo,
+    ) -> Result<u8, ProgramError> {
+        if *token_program.key != spl_token::id() {
+            msg!("[Paystream] Incorrect token program {}", token_program.key);
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        if *vault.key != stream_data.vault_pubkey {
+            msg!("[Paystream] Vault doesn't match stream");
+            return Err(ProgramError::from(PaystreamError::InvalidVault));
+        }
+
+        let (vault_authority_key, bump_seed) = find_vault_authority_address(program_id, stream_key);
+        if *authority.key != vault_authority_key {
+            msg!("[Paystream] Invalid vault authority");
+            return Err(ProgramError::from(PaystreamError::InvalidVault));
+        }
+
+        Ok(bump_seed)
+    }
+}
@@ -1,43 +1,904 @@
+// Enum defaults are written out, `#[default]` on a variant needs a newer compiler than the BPF
+// toolchain of solana-program 1.7
+#![allow(clippy::derivable_impls)]
+
 use borsh::{BorshDeserialize, BorshSerialize};
 use solana_program::{
+    clock::Clock,
+    entrypoint::ProgramResult,
+    program_error::ProgramError,
     program_pack::{IsInitialized, Sealed},
     pubkey::Pubkey,
 };
+use std::convert::TryFrom;
+
+use crate::{error::PaystreamError, instruction::StreamTerms};
+
+/// Marks an account as a stream of this program, followed by the layout version
+pub const STREAM_DISCRIMINATOR: [u8; 8] = *b"PAYSTRM\0";
+
+/// Version of the stream layout written by this program, 0 being the unversioned layout of the
+/// first streams
+pub const STREAM_VERSION: u8 = 1;
+
+/// Discriminator and version at the head of every stream account
+pub const STREAM_HEADER_LEN: usize = 9;
+
+/// Zeroed room at the end of every stream account. Fields appended to the layout later read as
+/// their defaults from it, so existing streams don't have to move.
+pub const STREAM_RESERVED_LEN: usize = 64;
 
 /// Rent Share Account state stored in the Agreement Account
-#[derive(BorshSerialize, BorshDeserialize, Debug)]
+#[derive(BorshSerialize, BorshDeserialize, Debug, Default, Clone)]
 pub struct StreamAccount {
-    pub status: u8,
+    pub status: StreamStatus,
     pub payee_pubkey: Pubkey,
     pub payer_pubkey: Pubkey,
+    /// Amount of the stream in lamports, or in base units of the mint for token streams, as are
+    /// all the other `*_lamports` amounts
     pub amount_in_lamports: u64,
+    /// Still held for the stream, not yet paid out or refunded
     pub remaining_lamports: u64,
-    pub duration_in_slots: u64,
-    pub start_timestamp_in_slots: u64,
+    /// Length of the stream in slots or seconds, depending on `schedule_mode`
+    pub duration: u64,
+    /// Start of the stream as a slot or unix timestamp, depending on `schedule_mode`
+    pub start_time: u64,
+    /// Mint of the streamed token, `Pubkey::default()` for native lamport streams
+    pub mint_pubkey: Pubkey,
+    /// Token account holding the escrowed tokens, `Pubkey::default()` for native lamport streams
+    pub vault_pubkey: Pubkey,
+    /// Total paid out to the payee so far
+    pub withdrawn_lamports: u64,
+    /// Who is allowed to cancel the stream
+    pub cancellation_policy: CancellationPolicy,
+    /// Unvested amount returned to the payer on cancellation
+    pub refunded_lamports: u64,
+    /// Whether the schedule is measured in slots or unix time
+    pub schedule_mode: ScheduleMode,
+    /// Nothing vests before this time, equal to `start_time` when there is no cliff
+    pub cliff_time: u64,
+    /// May pause and resume the stream besides the payer, `Pubkey::default()` if only the payer
+    pub pause_authority: Pubkey,
+    /// When the stream was paused, only meaningful while it is paused
+    pub paused_at: u64,
+    /// Total time the stream spent paused after its start, which doesn't count toward vesting
+    pub paused_duration: u64,
+    /// Accrued before `start_time` by earlier top-ups, the curve only covers the rest of the amount
+    pub rebased_amount: u64,
+    /// Whether the payee may hand the stream over to a new payee
+    pub transferable: bool,
+    /// Payee the stream was created for. The address of the stream stays derived from it, the
+    /// payer and `seed` after the stream was handed over to a new payee.
+    pub original_payee_pubkey: Pubkey,
+    /// Seed of the address of the stream, see `find_stream_address`
+    pub seed: u64,
+    /// Paid out of each push withdrawal to whoever submitted it
+    pub crank_tip: u64,
+    /// When the stream was last pushed, 0 if it never was
+    pub last_push_time: u64,
+    /// May cancel, pause, resume and top up the stream on behalf of the payer, such as a multisig
+    /// or governance PDA, `Pubkey::default()` if only the payer
+    pub authority: Pubkey,
+    /// Terms of a recurring stream, where `duration` is the length of a period and
+    /// `amount_in_lamports` what the payer deposited so far
+    pub recurrence: Option<Recurrence>,
+    /// Shape of the vesting between `start_time` and the end of the stream
+    pub vesting_curve: VestingCurve,
+    /// Recipients sharing the stream by weight, empty when everything goes to the payee
+    pub recipients: Vec<Recipient>,
 }
 
 impl Sealed for StreamAccount {}
 
 impl IsInitialized for StreamAccount {
     fn is_initialized(&self) -> bool {
-        self.status != StreamStatus::Uninitialized as u8
+        self.status != StreamStatus::Uninitialized
     }
 }
 
 impl StreamAccount {
+    /// Account size of a fixed stream without recipients on any curve but
+    /// `VestingCurve::Schedule`, see `packed_len`
+    pub const LEN: usize = 411;
+
+    /// Account size of the stream, header and reserved room included, which grows with the
+    /// recurrence, the checkpoints of a schedule curve and the recipients
+    pub fn packed_len(&self) -> std::io::Result<usize> {
+        Ok(STREAM_HEADER_LEN + self.try_to_vec()?.len() + STREAM_RESERVED_LEN)
+    }
+
+    /// Read the stream from the data of its account, which has to be of the current layout
+    pub fn load(data: &[u8]) -> Result<Self, ProgramError> {
+        match Self::load_any(data)? {
+            (stream, STREAM_VERSION) => Ok(stream),
+            _ => Err(ProgramError::from(PaystreamError::StreamNeedsMigration)),
+        }
+    }
+
+    /// Read the stream from the data of its account in any known layout, along with the version
+    /// of the layout
+    pub fn load_any(data: &[u8]) -> Result<(Self, u8), ProgramError> {
+        if data.len() == LegacyStreamAccount::LEN {
+            let legacy = LegacyStreamAccount::try_from_slice(data)?;
+            return Ok((Self::try_from(legacy)?, 0));
+        }
+
+        if data.get(..STREAM_DISCRIMINATOR.len()) != Some(&STREAM_DISCRIMINATOR[..]) {
+            return Err(ProgramError::UninitializedAccount);
+        }
+
+        match data[STREAM_DISCRIMINATOR.len()] {
+            STREAM_VERSION => Ok((
+                Self::deserialize(&mut &data[STREAM_HEADER_LEN..])?,
+                STREAM_VERSION,
+            )),
+            _ => Err(ProgramError::from(PaystreamError::UnknownStreamVersion)),
+        }
+    }
+
+    /// Write the stream in the current layout into the data of its account, zeroing the room
+    /// left behind it
+    pub fn store(&self, data: &mut [u8]) -> ProgramResult {
+        let body = self.try_to_vec()?;
+        let end = STREAM_HEADER_LEN + body.len();
+        if end > data.len() {
+            return Err(ProgramError::AccountDataTooSmall);
+        }
+
+        data[..STREAM_DISCRIMINATOR.len()].copy_from_slice(&STREAM_DISCRIMINATOR);
+        data[STREAM_DISCRIMINATOR.len()] = STREAM_VERSION;
+        data[STREAM_HEADER_LEN..end].copy_from_slice(&body);
+        data[end..].fill(0);
+
+        Ok(())
+    }
+
+    pub fn is_token_stream(&self) -> bool {
+        self.mint_pubkey != Pubkey::default()
+    }
+
+    /// Current time in the units of the stream schedule
+    pub fn now(&self, clock: &Clock) -> Result<u64, PaystreamError> {
+        self.schedule_mode.now(clock)
+    }
+
+    /// End of the stream, in the units of the stream schedule. Recurring streams end after their
+    /// last period, or never if they have no limit.
+    pub fn end_time(&self) -> Result<u64, PaystreamError> {
+        let duration = match self.recurrence {
+            Some(Recurrence { max_periods: 0, .. }) => return Ok(u64::MAX),
+            Some(Recurrence { max_periods, .. }) => self
+                .duration
+                .checked_mul(max_periods)
+                .ok_or(PaystreamError::MathOverflow)?,
+            None => self.duration,
+        };
+        self.start_time
+            .checked_add(duration)
+            .ok_or(PaystreamError::MathOverflow)
+    }
+
+    pub fn is_recurring(&self) -> bool {
+        self.recurrence.is_some()
+    }
+
+    /// Everything the stream will ever pay out, `None` for recurring streams without a limit
+    pub fn total_amount(&self) -> Result<Option<u64>, PaystreamError> {
+        match self.recurrence {
+            Some(Recurrence { max_periods: 0, .. }) => Ok(None),
+            Some(Recurrence {
+                period_amount,
+                max_periods,
+            }) => period_amount
+                .checked_mul(max_periods)
+                .map(Some)
+                .ok_or(PaystreamError::MathOverflow),
+            None => Ok(Some(self.amount_in_lamports)),
+        }
+    }
+
+    /// Whether the payer deposited everything the stream will ever pay out
+    pub fn is_fully_funded(&self) -> Result<bool, PaystreamError> {
+        Ok(self.total_amount()? == Some(self.amount_in_lamports))
+    }
+
+    /// Amount vested at `now` along the vesting curve from `start_time`, with nothing vested
+    /// before the cliff and paused time not counted. Once the stream has ended everything is
+    /// vested, including the remainder of the integer division.
+    pub fn vested_amount(&self, now: u64) -> Result<u64, PaystreamError> {
+        if self.duration == 0 {
+            return Err(PaystreamError::ZeroDuration);
+        }
+
+        let now = self.vesting_time(now);
+        if now < self.cliff_time {
+            return Ok(0);
+        }
+
+        self.accrued_amount(now)
+    }
+
+    /// Amount accrued by `time` on the schedule, regardless of the cliff
+    fn accrued_amount(&self, time: u64) -> Result<u64, PaystreamError> {
+        if self.is_recurring() {
+            return Ok(self.recurring_accrued(time)?.min(self.amount_in_lamports));
+        }
+
+        let elapsed = time.saturating_sub(self.start_time);
+        if elapsed >= self.duration {
+            return Ok(self.amount_in_lamports);
+        }
+
+        let vesting = self
+            .amount_in_lamports
+            .checked_sub(self.rebased_amount)
+            .ok_or(PaystreamError::MathOverflow)?;
+        self.vesting_curve
+            .vested_amount(vesting, elapsed, self.duration)?
+            .checked_add(self.rebased_amount)
+            .ok_or(PaystreamError::MathOverflow)
+    }
+
+    /// Amount a recurring stream accrued by `time` at its rate, up to its last period but
+    /// regardless of what the payer deposited
+    fn recurring_accrued(&self, time: u64) -> Result<u64, PaystreamError> {
+        let period_amount = self.recurrence.map_or(0, |recurrence| recurrence.period_amount);
+        let elapsed = time.saturating_sub(self.start_time);
+        let accrued = (period_amount as u128 * elapsed as u128)
+            .checked_div(self.duration as u128)
+            .ok_or(PaystreamError::ZeroDuration)?;
+        let accrued = u64::try_from(accrued).unwrap_or(u64::MAX);
+        Ok(match self.total_amount()? {
+            Some(total) => accrued.min(total),
+            None => accrued,
+        })
+    }
+
+    /// Mark a recurring stream insolvent once what it accrued at `now` reaches what the payer
+    /// deposited, and active again once refilled. The payee keeps its claim to everything
+    /// deposited either way.
+    pub fn update_solvency(&mut self, now: u64) -> Result<(), PaystreamError> {
+        if !self.is_recurring() || !(self.is_active() || self.is_insolvent()) {
+            return Ok(());
+        }
+
+        let accrued = self.recurring_accrued(self.vesting_time(now))?;
+        let status = if !self.is_fully_funded()? && accrued >= self.amount_in_lamports {
+            StreamStatus::Insolvent
+        } else {
+            StreamStatus::Active
+        };
+        if status != self.status {
+            self.transition(status)?;
+        }
+
+        Ok(())
+    }
+
+    /// Add `amount` to the stream at `now`. What accrued so far is kept as is and the schedule
+    /// restarts from now, either running longer at the current rate or paying the unvested
+    /// rest plus `amount` out faster over the time left.
+    /// Recurring streams keep their rate and schedule and are just refilled, see `refill`.
+    pub fn top_up(&mut self, amount: u64, mode: TopUpMode, now: u64) -> Result<(), PaystreamError> {
+        if self.is_recurring() {
+            return self.refill(amount);
+        }
+
+        if self.vesting_curve != VestingCurve::Linear {
+            return Err(PaystreamError::InvalidTopUp);
+        }
+
+        let now = self.vesting_time(now);
+        let accrued = self.accrued_amount(now)?;
+        let elapsed = now.saturating_sub(self.start_time).min(self.duration);
+        let time_left = self.duration - elapsed;
+
+        let duration = match mode {
+            TopUpMode::ExtendDuration => {
+                // Same rate as the current schedule, amount / duration
+                let vesting = self
+                    .amount_in_lamports
+                    .checked_sub(self.rebased_amount)
+                    .ok_or(PaystreamError::MathOverflow)?;
+                let extension = (amount as u128)
+                    .checked_mul(self.duration as u128)
+                    .ok_or(PaystreamError::MathOverflow)?
+                    .checked_div(vesting as u128)
+                    .ok_or(PaystreamError::InvalidTopUp)?;
+                u64::try_from(extension)
+                    .ok()
+                    .and_then(|extension| time_left.checked_add(extension))
+                    .ok_or(PaystreamError::MathOverflow)?
+            }
+            TopUpMode::IncreaseRate => time_left,
+        };
+        if duration == 0 {
+            return Err(PaystreamError::InvalidTopUp);
+        }
+
+        self.amount_in_lamports = self
+            .amount_in_lamports
+            .checked_add(amount)
+            .ok_or(PaystreamError::MathOverflow)?;
+        self.remaining_lamports = self
+            .remaining_lamports
+            .checked_add(amount)
+            .ok_or(PaystreamError::MathOverflow)?;
+        self.rebased_amount = accrued;
+        // A stream that already ended restarts now, so none of `amount` vests retroactively
+        self.start_time = self.start_time.max(now);
+        self.duration = duration;
+
+        Ok(())
+    }
+
+    /// Deposit `amount` more into a recurring stream, never more than it will ever pay out
+    fn refill(&mut self, amount: u64) -> Result<(), PaystreamError> {
+        let deposited = self
+            .amount_in_lamports
+            .checked_add(amount)
+            .ok_or(PaystreamError::MathOverflow)?;
+        if matches!(self.total_amount()?, Some(total) if deposited > total) {
+            return Err(PaystreamError::InvalidTopUp);
+        }
+
+        self.amount_in_lamports = deposited;
+        self.remaining_lamports = self
+            .remaining_lamports
+            .checked_add(amount)
+            .ok_or(PaystreamError::MathOverflow)?;
+
+        Ok(())
+    }
+
+    /// Time the schedule has reached at `now`, standing still while paused and shifted back by
+    /// earlier pauses
+    fn vesting_time(&self, now: u64) -> u64 {
+        let now = if self.is_paused() {
+            now.min(self.paused_at)
+        } else {
+            now
+        };
+        now.saturating_sub(self.paused_duration)
+    }
+
+    /// Amount the payee can withdraw at `now`, never more than is left of the stream
+    pub fn withdrawable_amount(&self, now: u64) -> Result<u64, PaystreamError> {
+        Ok(self
+            .vested_amount(now)?
+            .saturating_sub(self.withdrawn_lamports)
+            .min(self.remaining_lamports))
+    }
+
+    /// Whether pushing `amount` leaves the payee enough, the crank tip can't be more than
+    /// `MAX_CRANK_TIP_BPS` of a push
+    pub fn covers_crank_tip(&self, amount: u64) -> bool {
+        amount as u128 * MAX_CRANK_TIP_BPS as u128 >= self.crank_tip as u128 * 10_000
+    }
+
+    /// Earliest time the stream can be pushed again, `MIN_PUSH_INTERVAL_BPS` of the duration
+    /// after the last push
+    pub fn next_push_time(&self) -> u64 {
+        if self.last_push_time == 0 {
+            return 0;
+        }
+        let interval = self.duration as u128 * MIN_PUSH_INTERVAL_BPS as u128 / 10_000;
+        self.last_push_time.saturating_add(interval as u64)
+    }
+
+    pub fn is_split(&self) -> bool {
+        !self.recipients.is_empty()
+    }
+
+    pub fn recipient_index(&self, key: &Pubkey) -> Option<usize> {
+        self.recipients
+            .iter()
+            .position(|recipient| recipient.pubkey == *key)
+    }
+
+    /// Whether `key` is paid by the stream, the payee or for split streams one of the recipients
+    pub fn is_payee(&self, key: &Pubkey) -> bool {
+        if self.is_split() {
+            self.recipient_index(key).is_some()
+        } else {
+            self.payee_pubkey == *key
+        }
+    }
+
+    /// Share of `amount` due to the recipient at `index`. Shares are rounded down and the last
+    /// recipient gets the dust, so the shares always add up to `amount`.
+    pub fn recipient_share(&self, index: usize, amount: u64) -> Result<u64, PaystreamError> {
+        let share = |recipient: &Recipient| {
+            (amount as u128)
+                .checked_mul(recipient.weight_bps as u128)
+                .map(|scaled| (scaled / TOTAL_WEIGHT_BPS as u128) as u64)
+                .ok_or(PaystreamError::MathOverflow)
+        };
+
+        if index + 1 < self.recipients.len() {
+            return share(&self.recipients[index]);
+        }
+
+        let others = self.recipients[..index]
+            .iter()
+            .try_fold(0u64, |total, recipient| {
+                total
+                    .checked_add(share(recipient)?)
+                    .ok_or(PaystreamError::MathOverflow)
+            })?;
+        amount
+            .checked_sub(others)
+            .ok_or(PaystreamError::MathOverflow)
+    }
+
+    /// Vested but not yet withdrawn share of each recipient at `now`
+    pub fn owed_to_recipients(&self, now: u64) -> Result<Vec<u64>, PaystreamError> {
+        let vested = self.vested_amount(now)?;
+        self.recipients
+            .iter()
+            .enumerate()
+            .map(|(index, recipient)| {
+                Ok(self
+                    .recipient_share(index, vested)?
+                    .saturating_sub(recipient.withdrawn_lamports))
+            })
+            .collect()
+    }
+
+    /// Amount `payee` can withdraw at `now`, only its own share for split streams
+    pub fn withdrawable_amount_for(&self, payee: &Pubkey, now: u64) -> Result<u64, PaystreamError> {
+        if !self.is_split() {
+            return self.withdrawable_amount(now);
+        }
+
+        let index = self
+            .recipient_index(payee)
+            .ok_or(PaystreamError::InvalidPayee)?;
+        Ok(self
+            .recipient_share(index, self.vested_amount(now)?)?
+            .saturating_sub(self.recipients[index].withdrawn_lamports)
+            .min(self.remaining_lamports))
+    }
+
+    /// Recipients need distinct keys, non zero weights adding up to `TOTAL_WEIGHT_BPS` and
+    /// nothing withdrawn yet
+    pub fn validate_recipients(&self) -> Result<(), PaystreamError> {
+        if !self.is_split() {
+            return Ok(());
+        }
+
+        let total_weight = self
+            .recipients
+            .iter()
+            .try_fold(0u16, |total, recipient| total.checked_add(recipient.weight_bps));
+        let valid = self.recipients.len() <= MAX_RECIPIENTS
+            && total_weight == Some(TOTAL_WEIGHT_BPS)
+            && self.recipients.iter().enumerate().all(|(index, recipient)| {
+                recipient.pubkey != Pubkey::default()
+                    && recipient.weight_bps > 0
+                    && recipient.withdrawn_lamports == 0
+                    && self.recipient_index(&recipient.pubkey) == Some(index)
+            });
+
+        if valid {
+            Ok(())
+        } else {
+            Err(PaystreamError::InvalidRecipients)
+        }
+    }
+
+    /// Account for `amount` paid out to `payee`, completing the stream once everything is paid
+    /// out
+    pub fn record_withdrawal(&mut self, payee: &Pubkey, amount: u64) -> Result<(), PaystreamError> {
+        if let Some(index) = self.recipient_index(payee) {
+            let recipient = &mut self.recipients[index];
+            recipient.withdrawn_lamports = recipient
+                .withdrawn_lamports
+                .checked_add(amount)
+                .ok_or(PaystreamError::MathOverflow)?;
+        }
+
+        self.withdrawn_lamports = self
+            .withdrawn_lamports
+            .checked_add(amount)
+            .ok_or(PaystreamError::MathOverflow)?;
+        self.remaining_lamports = self
+            .remaining_lamports
+            .checked_sub(amount)
+            .ok_or(PaystreamError::InsufficientStreamBalance)?;
+
+        // The final payout after the end of the stream completes it, an insolvent recurring
+        // stream is only paid out up to what was deposited
+        if self.is_settled() && self.is_fully_funded()? {
+            self.transition(StreamStatus::Completed)?;
+        }
+
+        Ok(())
+    }
+
+    /// Nothing is owed to the payee anymore, so the stream can be closed
+    pub fn is_settled(&self) -> bool {
+        self.remaining_lamports == 0
+    }
+
+    /// Move the stream to `status`, as long as its current status allows it
+    pub fn transition(&mut self, status: StreamStatus) -> Result<(), PaystreamError> {
+        if !self.status.can_become(status) {
+            return Err(PaystreamError::InvalidStatusTransition);
+        }
+        self.status = status;
+
+        Ok(())
+    }
+
     pub fn is_complete(&self) -> bool {
-        self.status == StreamStatus::Completed as u8
+        self.status == StreamStatus::Completed
     }
 
     pub fn is_terminated(&self) -> bool {
-        self.status == StreamStatus::Terminated as u8
+        self.status == StreamStatus::Terminated
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.status == StreamStatus::Active
+    }
+
+    pub fn is_insolvent(&self) -> bool {
+        self.status == StreamStatus::Insolvent
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.status == StreamStatus::Paused
+    }
+
+    /// The payer and its authority can always pause the stream, and so can the pause authority if
+    /// there is one
+    pub fn can_pause(&self, signer: &Pubkey) -> bool {
+        self.acts_for_payer(signer)
+            || (self.pause_authority != Pubkey::default() && *signer == self.pause_authority)
+    }
+
+    /// Whether `signer` is the payer or the authority acting on its behalf
+    pub fn acts_for_payer(&self, signer: &Pubkey) -> bool {
+        *signer == self.payer_pubkey || self.is_authority(signer)
+    }
+
+    pub fn is_authority(&self, signer: &Pubkey) -> bool {
+        self.authority != Pubkey::default() && *signer == self.authority
     }
 }
 
-#[derive(Copy, Clone)]
+/// Unversioned layout of the first streams, upgraded to the current one by `Migrate`
+#[derive(BorshSerialize, BorshDeserialize, Debug, Default, Clone)]
+pub struct LegacyStreamAccount {
+    pub status: u8,
+    pub payee_pubkey: Pubkey,
+    pub payer_pubkey: Pubkey,
+    pub amount_in_lamports: u64,
+    pub remaining_lamports: u64,
+    pub duration_in_slots: u64,
+    pub start_timestamp_in_slots: u64,
+}
+
+impl LegacyStreamAccount {
+    pub const LEN: usize = 97;
+}
+
+impl TryFrom<LegacyStreamAccount> for StreamAccount {
+    type Error = ProgramError;
+
+    /// A linear lamport stream on slots that only the payee may cancel, as the old program
+    /// required its signature. The old layout didn't track payouts, so everything that left the
+    /// stream counts as withdrawn, refunds of terminated streams included.
+    fn try_from(legacy: LegacyStreamAccount) -> Result<Self, Self::Error> {
+        Ok(StreamAccount {
+            status: StreamStatus::try_from(legacy.status)?,
+            payee_pubkey: legacy.payee_pubkey,
+            payer_pubkey: legacy.payer_pubkey,
+            original_payee_pubkey: legacy.payee_pubkey,
+            amount_in_lamports: legacy.amount_in_lamports,
+            remaining_lamports: legacy.remaining_lamports,
+            duration: legacy.duration_in_slots,
+            start_time: legacy.start_timestamp_in_slots,
+            cliff_time: legacy.start_timestamp_in_slots,
+            withdrawn_lamports: legacy
+                .amount_in_lamports
+                .saturating_sub(legacy.remaining_lamports),
+            cancellation_policy: CancellationPolicy::PayeeOnly,
+            ..StreamAccount::default()
+        })
+    }
+}
+
+impl From<StreamTerms> for StreamAccount {
+    /// A stream on `terms` that is yet to be scheduled and funded
+    fn from(terms: StreamTerms) -> Self {
+        StreamAccount {
+            payee_pubkey: terms.payee_pubkey,
+            payer_pubkey: terms.payer_pubkey,
+            amount_in_lamports: terms.amount,
+            duration: terms.duration,
+            cancellation_policy: terms.cancellation_policy,
+            schedule_mode: terms.schedule_mode,
+            vesting_curve: terms.vesting_curve,
+            pause_authority: terms.pause_authority.unwrap_or_default(),
+            transferable: terms.transferable,
+            original_payee_pubkey: terms.payee_pubkey,
+            seed: terms.seed,
+            crank_tip: terms.crank_tip,
+            recipients: terms.recipients,
+            recurrence: terms.recurrence,
+            authority: terms.authority.unwrap_or_default(),
+            ..StreamAccount::default()
+        }
+    }
+}
+
+/// Most a crank tip can take of a push withdrawal, in basis points, i.e. 1%
+pub const MAX_CRANK_TIP_BPS: u16 = 100;
+
+/// Least time between two push withdrawals of a stream, in basis points of its duration
+pub const MIN_PUSH_INTERVAL_BPS: u16 = 100;
+
+/// Most recipients a split stream can have
+pub const MAX_RECIPIENTS: usize = 16;
+
+/// Weights of the recipients of a split stream add up to this, i.e. 100%
+pub const TOTAL_WEIGHT_BPS: u16 = 10_000;
+
+/// Recipient of a share of a split stream
+#[derive(BorshSerialize, BorshDeserialize, Debug, Default, Copy, Clone, PartialEq)]
+pub struct Recipient {
+    pub pubkey: Pubkey,
+    /// Share of the stream in basis points
+    pub weight_bps: u16,
+    /// Total paid out to this recipient so far
+    pub withdrawn_lamports: u64,
+}
+
+/// Most checkpoints a `VestingCurve::Schedule` can hold
+pub const MAX_VESTING_CHECKPOINTS: usize = 32;
+
+/// Highest exponent of the weighted vesting curves
+pub const MAX_CURVE_EXPONENT: u8 = 8;
+
+/// Shape of the vesting over the duration of a stream, chosen at creation
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
+pub enum VestingCurve {
+    /// Vests evenly over the duration
+    Linear,
+    /// Vests linearly but only unlocks at the end of each `period`, like a monthly payroll
+    Stepped { period: u64 },
+    /// Vests fastest at the start, `1 - (1 - t)^exponent` of the amount at fraction `t` of the
+    /// duration
+    FrontWeighted { exponent: u8 },
+    /// Vests fastest towards the end, `t^exponent` of the amount at fraction `t` of the duration
+    BackWeighted { exponent: u8 },
+    /// Vests the cumulative amount of the latest checkpoint reached, with checkpoints in
+    /// increasing order of offset
+    Schedule(Vec<VestingCheckpoint>),
+}
+
+impl Default for VestingCurve {
+    fn default() -> Self {
+        VestingCurve::Linear
+    }
+}
+
+/// Cumulative `amount` vested once `offset` has elapsed since the start of the stream
+#[derive(BorshSerialize, BorshDeserialize, Debug, Default, Copy, Clone, PartialEq)]
+pub struct VestingCheckpoint {
+    pub offset: u64,
+    pub amount: u64,
+}
+
+impl VestingCurve {
+    /// Check the curve fits a stream of `amount` over `duration`
+    pub fn validate(&self, amount: u64, duration: u64) -> Result<(), PaystreamError> {
+        let valid = match self {
+            VestingCurve::Linear => true,
+            VestingCurve::Stepped { period } => *period > 0 && *period <= duration,
+            VestingCurve::FrontWeighted { exponent } | VestingCurve::BackWeighted { exponent } => {
+                (1..=MAX_CURVE_EXPONENT).contains(exponent)
+            }
+            VestingCurve::Schedule(checkpoints) => {
+                !checkpoints.is_empty()
+                    && checkpoints.len() <= MAX_VESTING_CHECKPOINTS
+                    && checkpoints
+                        .windows(2)
+                        .all(|pair| pair[0].offset < pair[1].offset && pair[0].amount <= pair[1].amount)
+                    && matches!(
+                        checkpoints.last(),
+                        Some(last) if last.offset <= duration && last.amount <= amount
+                    )
+            }
+        };
+
+        if valid {
+            Ok(())
+        } else {
+            Err(PaystreamError::InvalidVestingCurve)
+        }
+    }
+
+    /// Amount of `amount` vested after `elapsed` out of `duration`, for `elapsed < duration`
+    pub fn vested_amount(&self, amount: u64, elapsed: u64, duration: u64) -> Result<u64, PaystreamError> {
+        match self {
+            VestingCurve::Linear => Self::scale(amount, elapsed, duration),
+            VestingCurve::Stepped { period } => {
+                let unlocked = elapsed
+                    .checked_rem(*period)
+                    .ok_or(PaystreamError::InvalidVestingCurve)?;
+                Self::scale(amount, elapsed - unlocked, duration)
+            }
+            VestingCurve::FrontWeighted { exponent } => {
+                let unvested = Self::power(amount, duration - elapsed, duration, *exponent)?;
+                Ok(amount - unvested)
+            }
+            VestingCurve::BackWeighted { exponent } => {
+                Self::power(amount, elapsed, duration, *exponent)
+            }
+            VestingCurve::Schedule(checkpoints) => Ok(checkpoints
+                .iter()
+                .rev()
+                .find(|checkpoint| checkpoint.offset <= elapsed)
+                .map_or(0, |checkpoint| checkpoint.amount.min(amount))),
+        }
+    }
+
+    /// `amount * numerator / denominator` for `numerator <= denominator`, so the result always
+    /// fits in a u64
+    fn scale(amount: u64, numerator: u64, denominator: u64) -> Result<u64, PaystreamError> {
+        let scaled = (amount as u128)
+            .checked_mul(numerator as u128)
+            .ok_or(PaystreamError::MathOverflow)?
+            .checked_div(denominator as u128)
+            .ok_or(PaystreamError::ZeroDuration)?;
+        Ok(scaled as u64)
+    }
+
+    /// `amount * (numerator / denominator)^exponent`, scaling one factor at a time so the
+    /// intermediate never exceeds `amount * numerator`
+    fn power(amount: u64, numerator: u64, denominator: u64, exponent: u8) -> Result<u64, PaystreamError> {
+        (0..exponent).try_fold(amount, |scaled, _| Self::scale(scaled, numerator, denominator))
+    }
+}
+
+/// Lifecycle of a stream, see `can_become` for the allowed transitions. Unknown values fail to
+/// decode.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Copy, Clone, PartialEq, Eq)]
 pub enum StreamStatus {
-    Uninitialized = 0,
+    Uninitialized,
     Active,
     Completed,
     Terminated,
+    Paused,
+    /// A recurring stream that accrued everything the payer deposited
+    Insolvent,
+}
+
+impl Default for StreamStatus {
+    fn default() -> Self {
+        StreamStatus::Uninitialized
+    }
+}
+
+impl StreamStatus {
+    /// Whether a stream may go from this status to `next`. Completed and terminated streams are
+    /// final, all that is left is to close them.
+    pub fn can_become(self, next: StreamStatus) -> bool {
+        use StreamStatus::*;
+
+        matches!(
+            (self, next),
+            (Uninitialized, Active)
+                | (Active, Paused | Completed | Terminated | Insolvent)
+                | (Paused, Active | Completed | Terminated)
+                | (Insolvent, Active | Completed | Terminated)
+        )
+    }
+}
+
+impl TryFrom<u8> for StreamStatus {
+    type Error = ProgramError;
+
+    fn try_from(status: u8) -> Result<Self, Self::Error> {
+        match status {
+            0 => Ok(StreamStatus::Uninitialized),
+            1 => Ok(StreamStatus::Active),
+            2 => Ok(StreamStatus::Completed),
+            3 => Ok(StreamStatus::Terminated),
+            4 => Ok(StreamStatus::Paused),
+            5 => Ok(StreamStatus::Insolvent),
+            _ => Err(ProgramError::InvalidAccountData),
+        }
+    }
+}
+
+/// Which party may cancel a stream, chosen at creation
+#[derive(BorshSerialize, BorshDeserialize, Debug, Copy, Clone, PartialEq)]
+pub enum CancellationPolicy {
+    /// Payer or payee
+    Either,
+    PayerOnly,
+    PayeeOnly,
+    /// Nobody, the stream always runs to the end
+    Irrevocable,
+}
+
+impl Default for CancellationPolicy {
+    fn default() -> Self {
+        CancellationPolicy::Either
+    }
+}
+
+impl CancellationPolicy {
+    pub fn allows(&self, payer_signed: bool, payee_signed: bool) -> bool {
+        match self {
+            CancellationPolicy::Either => payer_signed || payee_signed,
+            CancellationPolicy::PayerOnly => payer_signed,
+            CancellationPolicy::PayeeOnly => payee_signed,
+            CancellationPolicy::Irrevocable => false,
+        }
+    }
+}
+
+/// Terms of a recurring stream, which vests `period_amount` every period for as long as the
+/// payer keeps it funded
+#[derive(BorshSerialize, BorshDeserialize, Debug, Copy, Clone, PartialEq)]
+pub struct Recurrence {
+    pub period_amount: u64,
+    /// Stop after this many periods, 0 to renew until cancelled
+    pub max_periods: u64,
+}
+
+impl Recurrence {
+    /// Recurring streams accrue linearly and can't be funded beyond their last period
+    pub fn validate(&self, deposit: u64, vesting_curve: &VestingCurve) -> Result<(), PaystreamError> {
+        let total = self.period_amount.checked_mul(self.max_periods);
+        let valid = self.period_amount > 0
+            && *vesting_curve == VestingCurve::Linear
+            && (self.max_periods == 0 || matches!(total, Some(total) if deposit <= total));
+
+        if valid {
+            Ok(())
+        } else {
+            Err(PaystreamError::InvalidRecurrence)
+        }
+    }
+}
+
+/// How a top-up is folded into the schedule of a stream
+#[derive(BorshSerialize, BorshDeserialize, Debug, Copy, Clone, PartialEq)]
+pub enum TopUpMode {
+    /// Keep the rate and run for longer
+    ExtendDuration,
+    /// Keep the end and vest faster until then
+    IncreaseRate,
+}
+
+/// Clock a stream schedule is measured against, chosen at creation
+#[derive(BorshSerialize, BorshDeserialize, Debug, Copy, Clone, PartialEq)]
+pub enum ScheduleMode {
+    /// `Clock::slot`
+    Slot,
+    /// `Clock::unix_timestamp`, in seconds
+    UnixTimestamp,
+}
+
+impl Default for ScheduleMode {
+    fn default() -> Self {
+        ScheduleMode::Slot
+    }
+}
+
+impl ScheduleMode {
+    pub fn now(&self, clock: &Clock) -> Result<u64, PaystreamError> {
+        match self {
+            ScheduleMode::Slot => Ok(clock.slot),
+            ScheduleMode::UnixTimestamp => {
+                u64::try_from(clock.unix_timestamp).map_err(|_| PaystreamError::InvalidTimestamp)
+            }
+        }
+    }
 }
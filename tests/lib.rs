@@ -1,19 +1,33 @@
 use paystream::entrypoint::process_instruction;
-use borsh::BorshDeserialize;
+use borsh::BorshSerialize;
 use solana_program_test::*;
 use solana_sdk::{
     account::Account,
-    instruction::Instruction,
+    instruction::{AccountMeta, Instruction, InstructionError},
+    program_pack::Pack,
     pubkey::Pubkey,
     signature::Signer,
-    transaction::Transaction,
+    system_instruction,
+    transaction::{Transaction, TransactionError},
 };
 
+use solana_program::clock::Clock;
 use solana_program::hash::Hash;
+use solana_program::rent::Rent;
 use solana_sdk::signature::Keypair;
 use solana_sdk::transport::TransportError;
-use paystream::instruction::{create, cancel, PaystreamInstruction, withdrawal};
-use paystream::state::{StreamAccount, StreamStatus};
+use paystream::{find_stream_address, find_vault_authority_address};
+use paystream::instruction::{
+    cancel, cancel_split, cancel_token, close, close_token, create, create_batch, create_batches, create_token_stream, migrate, pause,
+    push_withdrawal, resume, top_up, transfer_payee, withdrawal, withdrawal_token, BatchStream, PaystreamInstruction,
+    StreamTerms, MAX_BATCH_STREAMS, MAX_COMPUTE_UNITS, MAX_TRANSACTION_SIZE,
+};
+use paystream::error::PaystreamError;
+use paystream::event::{decode_log, decode_logs, EventError, PaystreamEvent, EVENT_LOG_PREFIX, EVENT_VERSION};
+use paystream::state::{
+    CancellationPolicy, LegacyStreamAccount, Recipient, Recurrence, ScheduleMode, StreamAccount, StreamStatus,
+    TopUpMode, VestingCheckpoint, VestingCurve, STREAM_DISCRIMINATOR, STREAM_VERSION,
+};
 
 pub async fn sign_send_instruction(
     ctx: &mut ProgramTestContext,
@@ -29,26 +43,98 @@ pub async fn sign_send_instruction(
     ctx.banks_client.process_transaction(transaction).await
 }
 
-fn add_stream_account(program_id: Pubkey, program_test: &mut ProgramTest, stream_key: &Keypair, amount: u64) {
-    //TODO calculate lamports for rent for stream account
-    let rent_exemption = 10_000_000;
-    // Add stream account
-    program_test.add_account(
-        stream_key.pubkey(),
-        Account {
-            lamports: amount + rent_exemption,
-            data: vec![0_u8; 97],
-            owner: program_id,
-            ..Account::default()
-        },
-    );
+fn assert_paystream_error(result: Result<(), TransportError>, error: PaystreamError) {
+    match result {
+        Err(TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(code),
+        ))) => assert_eq!(code, error as u32),
+        other => panic!("Expected {:?}, got {:?}", error, other),
+    }
+}
+
+fn stream_address(program_id: Pubkey, payer_key: &Keypair, payee_key: &Keypair) -> Pubkey {
+    find_stream_address(&program_id, &payer_key.pubkey(), &payee_key.pubkey(), 0).0
 }
 
-async fn get_stream_account(banks_client: &mut BanksClient, stream_key: &Keypair) -> StreamAccount {
+async fn get_stream_account(banks_client: &mut BanksClient, stream_key: &Pubkey) -> StreamAccount {
     let stream_data = banks_client
-        .get_account(stream_key.pubkey()).await.unwrap().unwrap();
+        .get_account(*stream_key).await.unwrap().unwrap();
+
+    StreamAccount::load(stream_data.data.as_slice()).unwrap()
+}
+
+async fn create_mint(ctx: &mut ProgramTestContext, mint_key: &Keypair, authority: &Pubkey) {
+    let rent = ctx.banks_client.get_rent().await.unwrap();
+    let instructions = [
+        system_instruction::create_account(
+            &ctx.payer.pubkey(),
+            &mint_key.pubkey(),
+            rent.minimum_balance(spl_token::state::Mint::LEN),
+            spl_token::state::Mint::LEN as u64,
+            &spl_token::id(),
+        ),
+        spl_token::instruction::initialize_mint(
+            &spl_token::id(),
+            &mint_key.pubkey(),
+            authority,
+            None,
+            0,
+        ).unwrap(),
+    ];
+    let mut transaction = Transaction::new_with_payer(&instructions, Some(&ctx.payer.pubkey()));
+    transaction.sign(&[&ctx.payer, mint_key], ctx.last_blockhash);
+    ctx.banks_client.process_transaction(transaction).await.unwrap();
+}
 
-    StreamAccount::try_from_slice(stream_data.data.as_slice()).unwrap()
+async fn create_token_account(
+    ctx: &mut ProgramTestContext,
+    account_key: &Keypair,
+    mint: &Pubkey,
+    owner: &Pubkey,
+) {
+    let rent = ctx.banks_client.get_rent().await.unwrap();
+    let instructions = [
+        system_instruction::create_account(
+            &ctx.payer.pubkey(),
+            &account_key.pubkey(),
+            rent.minimum_balance(spl_token::state::Account::LEN),
+            spl_token::state::Account::LEN as u64,
+            &spl_token::id(),
+        ),
+        spl_token::instruction::initialize_account(
+            &spl_token::id(),
+            &account_key.pubkey(),
+            mint,
+            owner,
+        ).unwrap(),
+    ];
+    let mut transaction = Transaction::new_with_payer(&instructions, Some(&ctx.payer.pubkey()));
+    transaction.sign(&[&ctx.payer, account_key], ctx.last_blockhash);
+    ctx.banks_client.process_transaction(transaction).await.unwrap();
+}
+
+async fn mint_tokens(
+    ctx: &mut ProgramTestContext,
+    mint: &Pubkey,
+    account: &Pubkey,
+    mint_authority: &Keypair,
+    amount: u64,
+) {
+    let instruction = spl_token::instruction::mint_to(
+        &spl_token::id(),
+        mint,
+        account,
+        &mint_authority.pubkey(),
+        &[],
+        amount,
+    ).unwrap();
+    sign_send_instruction(ctx, instruction, vec![mint_authority]).await.unwrap();
+}
+
+async fn get_token_balance(banks_client: &mut BanksClient, account: &Pubkey) -> u64 {
+    let account = banks_client.get_account(*account).await.unwrap().unwrap();
+    spl_token::state::Account::unpack(&account.data).unwrap().amount
 }
 
 fn create_program_test() -> (Pubkey, ProgramTest, Keypair, Keypair) {
@@ -85,38 +171,48 @@ fn create_program_test() -> (Pubkey, ProgramTest, Keypair, Keypair) {
     (program_id, program_test, payer_key, payee_key)
 }
 
+/// Terms of a linear lamport stream on slots, every other term left at its default for tests
+/// to override the ones they exercise
+fn stream_terms(payer_key: &Keypair, payee_key: &Keypair, amount: u64, duration: u64) -> StreamTerms {
+    StreamTerms {
+        payee_pubkey: payee_key.pubkey(),
+        payer_pubkey: payer_key.pubkey(),
+        amount,
+        duration,
+        ..StreamTerms::default()
+    }
+}
+
+/// Create instruction for a lamport stream on `terms`, at the address of its parties and seed
+fn create_stream_instruction(program_id: Pubkey, terms: StreamTerms) -> Instruction {
+    let (stream_key, _) = find_stream_address(&program_id, &terms.payer_pubkey, &terms.payee_pubkey, terms.seed);
+    let payer_pubkey = terms.payer_pubkey;
+    create(program_id, PaystreamInstruction::Create { terms }, stream_key, payer_pubkey).unwrap()
+}
+
 fn create_stream_transaction(program_id: Pubkey, 
     payee_key: &Keypair, 
     payer_key: &Keypair, 
-    stream_key: &Keypair, 
     amount: u64, 
-    duration_in_slots: u64,
+    duration: u64,
     payer: &Keypair, 
     recent_blockhash: Hash
 ) -> Transaction {
     // Create stream instruction
-    let create_stream_instruction = create(
-        program_id,
-        PaystreamInstruction::Create {
-            payee_pubkey: payee_key.pubkey(),
-            payer_pubkey: payer_key.pubkey(),
-            amount,
-            duration_in_slots,
-        },
-        stream_key.pubkey(),
-    ).unwrap();
+    let create_stream_instruction =
+        create_stream_instruction(program_id, stream_terms(payer_key, payee_key, amount, duration));
 
     let mut transaction = Transaction::new_with_payer(
             &[create_stream_instruction],
                       Some(&payer.pubkey()));
 
-    transaction.sign(&[payer, stream_key], recent_blockhash);
+    transaction.sign(&[payer, payer_key], recent_blockhash);
 
     transaction
 }
 
 fn withdrawal_stream_transaction(program_id: Pubkey, 
-    stream_key: &Keypair, 
+    stream_key: &Pubkey, 
     payee_key: &Keypair, 
     payer: &Keypair,
     amount: u64,
@@ -128,23 +224,25 @@ fn withdrawal_stream_transaction(program_id: Pubkey,
         PaystreamInstruction::Withdrawal {
             amount
         },
-        stream_key.pubkey(),
+        *stream_key,
         payee_key.pubkey(),
+        None,
     ).unwrap();
 
     let mut transaction = Transaction::new_with_payer(
             &[withdrawal_stream_instruction],
                     Some(&payer.pubkey()));
 
-    transaction.sign(&[stream_key, payee_key, payer], recent_blockhash);
+    transaction.sign(&[payee_key, payer], recent_blockhash);
 
     transaction
 }
 
 fn cancel_stream_transaction(program_id: Pubkey, 
-    stream_key: &Keypair, 
+    stream_key: &Pubkey, 
     payee_key: &Keypair, 
     payer_key: &Keypair,
+    signer_key: &Keypair,
     payer: &Keypair,
     recent_blockhash: Hash
 ) -> Transaction {
@@ -152,34 +250,33 @@ fn cancel_stream_transaction(program_id: Pubkey,
     let cancel_stream_instruction = cancel(
         program_id,
         PaystreamInstruction::Cancel {},
-        stream_key.pubkey(),
+        *stream_key,
         payee_key.pubkey(),
         payer_key.pubkey(),
+        signer_key.pubkey(),
     ).unwrap();
 
     let mut transaction = Transaction::new_with_payer(
             &[cancel_stream_instruction],
                     Some(&payer.pubkey()));
 
-    transaction.sign(&[stream_key, payee_key, payer], recent_blockhash);
+    transaction.sign(&[signer_key, payer], recent_blockhash);
 
     transaction
 }
 
 #[tokio::test]
 async fn should_cancel_stream() {
-    let (program_id, mut program_test, payer_key, payee_key) = create_program_test();
-    let stream_key = Keypair::new();
+    let (program_id, program_test, payer_key, payee_key) = create_program_test();
+    let stream_key = stream_address(program_id, &payer_key, &payee_key);
     let amount = 1000;
     let duration = 10;
-    add_stream_account(program_id, &mut program_test, &stream_key, amount);
    
     let (mut banks_client, payer, recent_blockhash) = program_test.start().await;
 
     let transaction = create_stream_transaction(program_id, 
         &payee_key, 
         &payer_key, 
-        &stream_key, 
         amount,
         duration,
         &payer, 
@@ -192,6 +289,7 @@ async fn should_cancel_stream() {
         &stream_key,
         &payee_key, 
         &payer_key, 
+        &payee_key, 
         &payer, 
         recent_blockhash
     );
@@ -199,24 +297,22 @@ async fn should_cancel_stream() {
     banks_client.process_transaction(transaction).await.unwrap();
 
     let stream = get_stream_account(&mut banks_client, &stream_key).await;
-    assert_eq!(stream.status, StreamStatus::Terminated as u8);
+    assert_eq!(stream.status, StreamStatus::Terminated);
 }
 
 #[tokio::test]
 async fn should_create_stream() {
-    let (program_id, mut program_test, payer_key, payee_key) = create_program_test();
+    let (program_id, program_test, payer_key, payee_key) = create_program_test();
     
-    let stream_key = Keypair::new();
+    let stream_key = stream_address(program_id, &payer_key, &payee_key);
     let amount = 1000;
     let duration = 10;
-    add_stream_account(program_id, &mut program_test, &stream_key, amount);
     
     let (mut banks_client, payer, recent_blockhash) = program_test.start().await;
 
     let transaction = create_stream_transaction(program_id, 
         &payee_key, 
         &payer_key, 
-        &stream_key, 
         amount,
         duration,
         &payer, 
@@ -225,26 +321,69 @@ async fn should_create_stream() {
     
     banks_client.process_transaction(transaction).await.unwrap();
 
+    let account = banks_client.get_account(stream_key).await.unwrap().unwrap();
+    assert_eq!(account.owner, program_id);
+    assert_eq!(account.data.len(), StreamAccount::LEN);
+
     let stream = get_stream_account(&mut banks_client, &stream_key).await;
     assert_eq!(stream.remaining_lamports, amount);
 }
 
+#[tokio::test]
+async fn should_create_stream_at_prefunded_address() {
+    let (program_id, mut program_test, payer_key, payee_key) = create_program_test();
+
+    // Lamports sent to the address ahead of time don't block the stream
+    let stream_key = stream_address(program_id, &payer_key, &payee_key);
+    program_test.add_account(
+        stream_key,
+        Account {
+            lamports: 1,
+            ..Account::default()
+        },
+    );
+    let amount = 1000;
+    let duration = 10;
+
+    let (mut banks_client, payer, recent_blockhash) = program_test.start().await;
+    let payer_balance = banks_client.get_balance(payer_key.pubkey()).await.unwrap();
+
+    let transaction = create_stream_transaction(program_id,
+        &payee_key,
+        &payer_key,
+        amount,
+        duration,
+        &payer,
+        recent_blockhash
+    );
+    banks_client.process_transaction(transaction).await.unwrap();
+
+    let account = banks_client.get_account(stream_key).await.unwrap().unwrap();
+    let stream_rent = banks_client.get_rent().await.unwrap().minimum_balance(StreamAccount::LEN);
+    assert_eq!(account.owner, program_id);
+    assert_eq!(account.data.len(), StreamAccount::LEN);
+    assert_eq!(account.lamports, stream_rent + amount);
+    assert_eq!(
+        banks_client.get_balance(payer_key.pubkey()).await.unwrap(),
+        payer_balance - stream_rent - amount + 1
+    );
+    assert_eq!(get_stream_account(&mut banks_client, &stream_key).await.remaining_lamports, amount);
+}
+
 #[tokio::test]
 async fn should_withdrawal_from_stream() {
     // Create program test
-    let (program_id, mut program_test, payer_key, payee_key) = create_program_test();
+    let (program_id, program_test, payer_key, payee_key) = create_program_test();
 
-    let stream_key = Keypair::new();
+    let stream_key = stream_address(program_id, &payer_key, &payee_key);
     let amount = 1000;
     let duration = 10;
-    add_stream_account(program_id, &mut program_test, &stream_key, amount);
     
     let mut ctx = program_test.start_with_context().await;
 
     let transaction = create_stream_transaction(program_id, 
         &payee_key, 
         &payer_key, 
-        &stream_key, 
         amount, 
         duration,
         &ctx.payer, 
@@ -270,3 +409,2248 @@ async fn should_withdrawal_from_stream() {
     let stream = get_stream_account(&mut ctx.banks_client, &stream_key).await;
     assert_eq!(stream.remaining_lamports, amount / 2);
 }
+
+#[tokio::test]
+async fn should_stream_tokens() {
+    let (program_id, program_test, payer_key, payee_key) = create_program_test();
+    let stream_key = stream_address(program_id, &payer_key, &payee_key);
+    let amount = 1000;
+    let duration = 10;
+
+    let mut ctx = program_test.start_with_context().await;
+
+    let mint_key = Keypair::new();
+    let vault_key = Keypair::new();
+    let payer_token_key = Keypair::new();
+    let payee_token_key = Keypair::new();
+    let (vault_authority, _) = find_vault_authority_address(&program_id, &stream_key);
+
+    create_mint(&mut ctx, &mint_key, &payer_key.pubkey()).await;
+    create_token_account(&mut ctx, &vault_key, &mint_key.pubkey(), &vault_authority).await;
+    create_token_account(&mut ctx, &payer_token_key, &mint_key.pubkey(), &payer_key.pubkey()).await;
+    create_token_account(&mut ctx, &payee_token_key, &mint_key.pubkey(), &payee_key.pubkey()).await;
+    mint_tokens(&mut ctx, &mint_key.pubkey(), &payer_token_key.pubkey(), &payer_key, amount).await;
+
+    let instruction = create_token_stream(
+        program_id,
+        PaystreamInstruction::CreateTokenStream {
+            terms: stream_terms(&payer_key, &payee_key, amount, duration),
+        },
+        stream_key,
+        vault_key.pubkey(),
+        payer_token_key.pubkey(),
+        payer_key.pubkey(),
+        mint_key.pubkey(),
+    ).unwrap();
+    sign_send_instruction(&mut ctx, instruction, vec![&payer_key]).await.unwrap();
+
+    assert_eq!(get_token_balance(&mut ctx.banks_client, &vault_key.pubkey()).await, amount);
+    let stream = get_stream_account(&mut ctx.banks_client, &stream_key).await;
+    assert!(stream.is_token_stream());
+    assert_eq!(stream.mint_pubkey, mint_key.pubkey());
+    assert_eq!(stream.vault_pubkey, vault_key.pubkey());
+
+    ctx.warp_to_slot(duration / 2).unwrap();
+
+    let instruction = withdrawal_token(
+        program_id,
+        PaystreamInstruction::Withdrawal { amount: 100 },
+        stream_key,
+        payee_key.pubkey(),
+        vault_key.pubkey(),
+        payee_token_key.pubkey(),
+    ).unwrap();
+    sign_send_instruction(&mut ctx, instruction, vec![&payee_key]).await.unwrap();
+
+    assert_eq!(get_token_balance(&mut ctx.banks_client, &payee_token_key.pubkey()).await, 100);
+
+    let instruction = cancel_token(
+        program_id,
+        PaystreamInstruction::Cancel {},
+        stream_key,
+        payee_key.pubkey(),
+        payer_key.pubkey(),
+        payer_key.pubkey(),
+        vault_key.pubkey(),
+        payee_token_key.pubkey(),
+        payer_token_key.pubkey(),
+    ).unwrap();
+    sign_send_instruction(&mut ctx, instruction, vec![&payer_key]).await.unwrap();
+
+    let stream = get_stream_account(&mut ctx.banks_client, &stream_key).await;
+    assert_eq!(stream.status, StreamStatus::Terminated);
+    assert_eq!(get_token_balance(&mut ctx.banks_client, &vault_key.pubkey()).await, 0);
+    assert_eq!(
+        get_token_balance(&mut ctx.banks_client, &payee_token_key.pubkey()).await,
+        stream.withdrawn_lamports
+    );
+    assert_eq!(
+        get_token_balance(&mut ctx.banks_client, &payer_token_key.pubkey()).await,
+        stream.refunded_lamports
+    );
+    assert_eq!(stream.withdrawn_lamports + stream.refunded_lamports, amount);
+}
+
+#[tokio::test]
+async fn should_close_token_stream_sweeping_vault() {
+    let (program_id, program_test, payer_key, payee_key) = create_program_test();
+    let stream_key = stream_address(program_id, &payer_key, &payee_key);
+    let amount = 1000;
+    let dust = 7;
+
+    let mut ctx = program_test.start_with_context().await;
+
+    let mint_key = Keypair::new();
+    let vault_key = Keypair::new();
+    let payer_token_key = Keypair::new();
+    let payee_token_key = Keypair::new();
+    let (vault_authority, _) = find_vault_authority_address(&program_id, &stream_key);
+
+    create_mint(&mut ctx, &mint_key, &payer_key.pubkey()).await;
+    create_token_account(&mut ctx, &vault_key, &mint_key.pubkey(), &vault_authority).await;
+    create_token_account(&mut ctx, &payer_token_key, &mint_key.pubkey(), &payer_key.pubkey()).await;
+    create_token_account(&mut ctx, &payee_token_key, &mint_key.pubkey(), &payee_key.pubkey()).await;
+    mint_tokens(&mut ctx, &mint_key.pubkey(), &payer_token_key.pubkey(), &payer_key, amount).await;
+
+    let instruction = create_token_stream(
+        program_id,
+        PaystreamInstruction::CreateTokenStream {
+            terms: stream_terms(&payer_key, &payee_key, amount, 10),
+        },
+        stream_key,
+        vault_key.pubkey(),
+        payer_token_key.pubkey(),
+        payer_key.pubkey(),
+        mint_key.pubkey(),
+    ).unwrap();
+    sign_send_instruction(&mut ctx, instruction, vec![&payer_key]).await.unwrap();
+
+    let instruction = cancel_token(
+        program_id,
+        PaystreamInstruction::Cancel {},
+        stream_key,
+        payee_key.pubkey(),
+        payer_key.pubkey(),
+        payer_key.pubkey(),
+        vault_key.pubkey(),
+        payee_token_key.pubkey(),
+        payer_token_key.pubkey(),
+    ).unwrap();
+    sign_send_instruction(&mut ctx, instruction, vec![&payer_key]).await.unwrap();
+    let payer_tokens = get_token_balance(&mut ctx.banks_client, &payer_token_key.pubkey()).await;
+
+    // Anyone can send tokens to the vault of a settled stream, which mustn't block closing it
+    mint_tokens(&mut ctx, &mint_key.pubkey(), &vault_key.pubkey(), &payer_key, dust).await;
+
+    let instruction = close_token(
+        program_id,
+        PaystreamInstruction::Close {},
+        stream_key,
+        payer_key.pubkey(),
+        payer_key.pubkey(),
+        vault_key.pubkey(),
+        payer_token_key.pubkey(),
+    ).unwrap();
+    sign_send_instruction(&mut ctx, instruction, vec![&payer_key]).await.unwrap();
+
+    assert!(ctx.banks_client.get_account(vault_key.pubkey()).await.unwrap().is_none());
+    assert!(ctx.banks_client.get_account(stream_key).await.unwrap().is_none());
+    assert_eq!(
+        get_token_balance(&mut ctx.banks_client, &payer_token_key.pubkey()).await,
+        payer_tokens + dust
+    );
+}
+
+#[tokio::test]
+async fn should_not_create_stream_with_forged_payer() {
+    let (program_id, program_test, payer_key, payee_key) = create_program_test();
+    let amount = 1000;
+    let duration = 10;
+
+    let mut ctx = program_test.start_with_context().await;
+
+    // The context payer signs and funds the stream, but names someone else as its payer
+    let instruction = create(
+        program_id,
+        PaystreamInstruction::Create { terms: stream_terms(&payer_key, &payee_key, amount, duration) },
+        stream_address(program_id, &payer_key, &payee_key),
+        ctx.payer.pubkey(),
+    ).unwrap();
+
+    let result = sign_send_instruction(&mut ctx, instruction, vec![]).await;
+    assert_paystream_error(result, PaystreamError::PayerNotSigner);
+}
+
+#[tokio::test]
+async fn should_not_create_stream_without_payer_signature() {
+    let (program_id, program_test, payer_key, payee_key) = create_program_test();
+    let amount = 1000;
+    let duration = 10;
+
+    let mut ctx = program_test.start_with_context().await;
+
+    let mut instruction = create(
+        program_id,
+        PaystreamInstruction::Create { terms: stream_terms(&payer_key, &payee_key, amount, duration) },
+        stream_address(program_id, &payer_key, &payee_key),
+        payer_key.pubkey(),
+    ).unwrap();
+    instruction.accounts[1].is_signer = false;
+
+    let result = sign_send_instruction(&mut ctx, instruction, vec![]).await;
+    assert!(matches!(
+        result,
+        Err(TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::MissingRequiredSignature,
+        )))
+    ));
+}
+
+#[test]
+fn should_vest_linearly_and_pay_out_dust_at_end() {
+    let stream = StreamAccount {
+        amount_in_lamports: 1000,
+        remaining_lamports: 1000,
+        duration: 3,
+        start_time: 10,
+        ..StreamAccount::default()
+    };
+
+    assert_eq!(stream.vested_amount(5).unwrap(), 0);
+    assert_eq!(stream.vested_amount(11).unwrap(), 333);
+    assert_eq!(stream.vested_amount(12).unwrap(), 666);
+    assert_eq!(stream.vested_amount(13).unwrap(), 1000);
+    assert_eq!(stream.vested_amount(100).unwrap(), 1000);
+
+    let stream = StreamAccount {
+        withdrawn_lamports: 666,
+        remaining_lamports: 334,
+        ..stream
+    };
+    assert_eq!(stream.withdrawable_amount(12).unwrap(), 0);
+    assert_eq!(stream.withdrawable_amount(13).unwrap(), 334);
+}
+
+#[tokio::test]
+async fn should_not_withdraw_vested_amount_twice() {
+    let (program_id, program_test, payer_key, payee_key) = create_program_test();
+    let stream_key = stream_address(program_id, &payer_key, &payee_key);
+    let amount = 1000;
+    let duration = 10;
+
+    let mut ctx = program_test.start_with_context().await;
+
+    let transaction = create_stream_transaction(program_id,
+        &payee_key,
+        &payer_key,
+        amount,
+        duration,
+        &ctx.payer,
+        ctx.last_blockhash
+    );
+    ctx.banks_client.process_transaction(transaction).await.unwrap();
+
+    ctx.warp_to_slot(duration / 2).unwrap();
+
+    // Ask for everything, only what has vested is paid out
+    let transaction = withdrawal_stream_transaction(
+        program_id,
+        &stream_key,
+        &payee_key,
+        &ctx.payer,
+        amount,
+        ctx.last_blockhash
+    );
+    ctx.banks_client.process_transaction(transaction).await.unwrap();
+
+    let stream = get_stream_account(&mut ctx.banks_client, &stream_key).await;
+    let vested = stream.withdrawn_lamports;
+    assert!(vested > 0 && vested < amount);
+    assert_eq!(stream.remaining_lamports, amount - vested);
+
+    // Nothing new has vested in the same slot
+    let transaction = withdrawal_stream_transaction(
+        program_id,
+        &stream_key,
+        &payee_key,
+        &ctx.payer,
+        amount - 1,
+        ctx.last_blockhash
+    );
+    ctx.banks_client.process_transaction(transaction).await.unwrap();
+
+    let stream = get_stream_account(&mut ctx.banks_client, &stream_key).await;
+    assert_eq!(stream.withdrawn_lamports, vested);
+
+    ctx.warp_to_slot(duration * 2).unwrap();
+
+    // Different amount from the first request so the transaction isn't a duplicate
+    let transaction = withdrawal_stream_transaction(
+        program_id,
+        &stream_key,
+        &payee_key,
+        &ctx.payer,
+        amount + 1,
+        ctx.last_blockhash
+    );
+    ctx.banks_client.process_transaction(transaction).await.unwrap();
+
+    let stream = get_stream_account(&mut ctx.banks_client, &stream_key).await;
+    assert_eq!(stream.withdrawn_lamports, amount);
+    assert_eq!(stream.remaining_lamports, 0);
+    assert_eq!(stream.status, StreamStatus::Completed);
+}
+
+#[tokio::test]
+async fn should_not_create_empty_stream() {
+    let (program_id, program_test, payer_key, payee_key) = create_program_test();
+    let (mut banks_client, payer, recent_blockhash) = program_test.start().await;
+
+    let transaction = create_stream_transaction(program_id,
+        &payee_key,
+        &payer_key,
+        0,
+        10,
+        &payer,
+        recent_blockhash
+    );
+    let result = banks_client.process_transaction(transaction).await;
+    assert_paystream_error(result, PaystreamError::InsufficientAmount);
+
+    let transaction = create_stream_transaction(program_id,
+        &payee_key,
+        &payer_key,
+        1000,
+        0,
+        &payer,
+        recent_blockhash
+    );
+    let result = banks_client.process_transaction(transaction).await;
+    assert_paystream_error(result, PaystreamError::ZeroDuration);
+}
+
+#[tokio::test]
+async fn should_split_vested_amount_when_payer_cancels() {
+    let (program_id, program_test, payer_key, payee_key) = create_program_test();
+    let stream_key = stream_address(program_id, &payer_key, &payee_key);
+    let amount = 1000;
+    let duration = 10;
+
+    let mut ctx = program_test.start_with_context().await;
+
+    let transaction = create_stream_transaction(program_id,
+        &payee_key,
+        &payer_key,
+        amount,
+        duration,
+        &ctx.payer,
+        ctx.last_blockhash
+    );
+    ctx.banks_client.process_transaction(transaction).await.unwrap();
+
+    ctx.warp_to_slot(duration / 2).unwrap();
+
+    let payee_balance = ctx.banks_client.get_balance(payee_key.pubkey()).await.unwrap();
+    let payer_balance = ctx.banks_client.get_balance(payer_key.pubkey()).await.unwrap();
+
+    let transaction = cancel_stream_transaction(program_id,
+        &stream_key,
+        &payee_key,
+        &payer_key,
+        &payer_key,
+        &ctx.payer,
+        ctx.last_blockhash
+    );
+    ctx.banks_client.process_transaction(transaction).await.unwrap();
+
+    let stream = get_stream_account(&mut ctx.banks_client, &stream_key).await;
+    assert_eq!(stream.status, StreamStatus::Terminated);
+    assert!(stream.withdrawn_lamports > 0 && stream.withdrawn_lamports < amount);
+    assert_eq!(stream.withdrawn_lamports + stream.refunded_lamports, amount);
+    assert_eq!(stream.remaining_lamports, 0);
+    assert_eq!(
+        ctx.banks_client.get_balance(payee_key.pubkey()).await.unwrap(),
+        payee_balance + stream.withdrawn_lamports
+    );
+    assert_eq!(
+        ctx.banks_client.get_balance(payer_key.pubkey()).await.unwrap(),
+        payer_balance + stream.refunded_lamports
+    );
+}
+
+#[tokio::test]
+async fn should_not_cancel_against_policy() {
+    let (program_id, program_test, payer_key, payee_key) = create_program_test();
+    let stream_key = stream_address(program_id, &payer_key, &payee_key);
+
+    let mut ctx = program_test.start_with_context().await;
+
+    let instruction = create_stream_instruction(
+        program_id,
+        StreamTerms {
+            cancellation_policy: CancellationPolicy::PayeeOnly,
+            ..stream_terms(&payer_key, &payee_key, 1000, 10)
+        },
+    );
+    sign_send_instruction(&mut ctx, instruction, vec![&payer_key]).await.unwrap();
+
+    let transaction = cancel_stream_transaction(program_id,
+        &stream_key,
+        &payee_key,
+        &payer_key,
+        &payer_key,
+        &ctx.payer,
+        ctx.last_blockhash
+    );
+    let result = ctx.banks_client.process_transaction(transaction).await;
+    assert_paystream_error(result, PaystreamError::CancellationNotAllowed);
+}
+
+#[tokio::test]
+async fn should_close_settled_stream() {
+    let (program_id, program_test, payer_key, payee_key) = create_program_test();
+    let stream_key = stream_address(program_id, &payer_key, &payee_key);
+    let amount = 1000;
+    let duration = 10;
+
+    let mut ctx = program_test.start_with_context().await;
+
+    let transaction = create_stream_transaction(program_id,
+        &payee_key,
+        &payer_key,
+        amount,
+        duration,
+        &ctx.payer,
+        ctx.last_blockhash
+    );
+    ctx.banks_client.process_transaction(transaction).await.unwrap();
+
+    // The payee is still owed the stream
+    let instruction = close(
+        program_id,
+        PaystreamInstruction::Close {},
+        stream_key,
+        payer_key.pubkey(),
+        payee_key.pubkey(),
+    ).unwrap();
+    let result = sign_send_instruction(&mut ctx, instruction, vec![&payer_key]).await;
+    assert_paystream_error(result, PaystreamError::StreamNotSettled);
+
+    let transaction = cancel_stream_transaction(program_id,
+        &stream_key,
+        &payee_key,
+        &payer_key,
+        &payer_key,
+        &ctx.payer,
+        ctx.last_blockhash
+    );
+    ctx.banks_client.process_transaction(transaction).await.unwrap();
+
+    let rent = ctx.banks_client.get_balance(stream_key).await.unwrap();
+    let payer_balance = ctx.banks_client.get_balance(payer_key.pubkey()).await.unwrap();
+
+    let instruction = close(
+        program_id,
+        PaystreamInstruction::Close {},
+        stream_key,
+        payer_key.pubkey(),
+        payer_key.pubkey(),
+    ).unwrap();
+    sign_send_instruction(&mut ctx, instruction, vec![&payer_key]).await.unwrap();
+
+    assert!(ctx.banks_client.get_account(stream_key).await.unwrap().is_none());
+    assert_eq!(
+        ctx.banks_client.get_balance(payer_key.pubkey()).await.unwrap(),
+        payer_balance + rent
+    );
+}
+
+#[tokio::test]
+async fn should_complete_stream() {
+    let (program_id, program_test, payer_key, payee_key) = create_program_test();
+    let stream_key = stream_address(program_id, &payer_key, &payee_key);
+    let amount = 1000;
+    let duration = 10;
+
+    let mut ctx = program_test.start_with_context().await;
+
+    let transaction = create_stream_transaction(program_id,
+        &payee_key,
+        &payer_key,
+        amount,
+        duration,
+        &ctx.payer,
+        ctx.last_blockhash
+    );
+    ctx.banks_client.process_transaction(transaction).await.unwrap();
+
+    let stream = get_stream_account(&mut ctx.banks_client, &stream_key).await;
+    assert_eq!(stream.status, StreamStatus::Active);
+
+    // Partial withdrawal after the end leaves the stream active
+    ctx.warp_to_slot(duration * 2).unwrap();
+    let transaction = withdrawal_stream_transaction(
+        program_id,
+        &stream_key,
+        &payee_key,
+        &ctx.payer,
+        amount / 2,
+        ctx.last_blockhash
+    );
+    ctx.banks_client.process_transaction(transaction).await.unwrap();
+
+    let stream = get_stream_account(&mut ctx.banks_client, &stream_key).await;
+    assert_eq!(stream.status, StreamStatus::Active);
+
+    // Paying out the rest completes it
+    let transaction = withdrawal_stream_transaction(
+        program_id,
+        &stream_key,
+        &payee_key,
+        &ctx.payer,
+        amount,
+        ctx.last_blockhash
+    );
+    ctx.banks_client.process_transaction(transaction).await.unwrap();
+
+    let stream = get_stream_account(&mut ctx.banks_client, &stream_key).await;
+    assert!(stream.is_complete());
+    assert_eq!(stream.withdrawn_lamports, amount);
+
+    let transaction = withdrawal_stream_transaction(
+        program_id,
+        &stream_key,
+        &payee_key,
+        &ctx.payer,
+        1,
+        ctx.last_blockhash
+    );
+    let result = ctx.banks_client.process_transaction(transaction).await;
+    assert_paystream_error(result, PaystreamError::StreamCompleted);
+
+    let transaction = cancel_stream_transaction(program_id,
+        &stream_key,
+        &payee_key,
+        &payer_key,
+        &payer_key,
+        &ctx.payer,
+        ctx.last_blockhash
+    );
+    let result = ctx.banks_client.process_transaction(transaction).await;
+    assert_paystream_error(result, PaystreamError::StreamCompleted);
+}
+
+#[test]
+fn should_vest_on_unix_timestamps() {
+    let stream = StreamAccount {
+        amount_in_lamports: 3600,
+        remaining_lamports: 3600,
+        duration: 3600,
+        start_time: 1_600_000_000,
+        schedule_mode: ScheduleMode::UnixTimestamp,
+        ..StreamAccount::default()
+    };
+
+    let clock = Clock {
+        slot: 5,
+        unix_timestamp: 1_600_000_060,
+        ..Clock::default()
+    };
+    let now = stream.now(&clock).unwrap();
+    assert_eq!(now, 1_600_000_060);
+    assert_eq!(stream.withdrawable_amount(now).unwrap(), 60);
+
+    let clock = Clock {
+        unix_timestamp: -1,
+        ..Clock::default()
+    };
+    assert!(stream.now(&clock).is_err());
+}
+
+#[tokio::test]
+async fn should_create_stream_on_unix_timestamps() {
+    let (program_id, program_test, payer_key, payee_key) = create_program_test();
+    let stream_key = stream_address(program_id, &payer_key, &payee_key);
+
+    let mut ctx = program_test.start_with_context().await;
+
+    let instruction = create_stream_instruction(
+        program_id,
+        StreamTerms {
+            schedule_mode: ScheduleMode::UnixTimestamp,
+            ..stream_terms(&payer_key, &payee_key, 1000, 60 * 60 * 24 * 30)
+        },
+    );
+    sign_send_instruction(&mut ctx, instruction, vec![&payer_key]).await.unwrap();
+
+    let stream = get_stream_account(&mut ctx.banks_client, &stream_key).await;
+    assert_eq!(stream.schedule_mode, ScheduleMode::UnixTimestamp);
+    // A unix timestamp, not a slot
+    assert!(stream.start_time > 1_000_000_000);
+}
+
+#[test]
+fn should_vest_after_start_and_cliff() {
+    let stream = StreamAccount {
+        amount_in_lamports: 1000,
+        remaining_lamports: 1000,
+        duration: 100,
+        start_time: 100,
+        cliff_time: 125,
+        ..StreamAccount::default()
+    };
+
+    // Before start
+    assert_eq!(stream.vested_amount(50).unwrap(), 0);
+    // Pre-cliff
+    assert_eq!(stream.vested_amount(110).unwrap(), 0);
+    assert_eq!(stream.vested_amount(124).unwrap(), 0);
+    // At the cliff everything vested so far unlocks as a lump sum
+    assert_eq!(stream.vested_amount(125).unwrap(), 250);
+    assert_eq!(stream.vested_amount(150).unwrap(), 500);
+    // Post-end
+    assert_eq!(stream.vested_amount(200).unwrap(), 1000);
+    assert_eq!(stream.vested_amount(1000).unwrap(), 1000);
+}
+
+#[tokio::test]
+async fn should_not_withdraw_before_cliff() {
+    let (program_id, program_test, payer_key, payee_key) = create_program_test();
+    let stream_key = stream_address(program_id, &payer_key, &payee_key);
+    let amount = 1000;
+
+    let mut ctx = program_test.start_with_context().await;
+
+    let instruction = create_stream_instruction(
+        program_id,
+        StreamTerms {
+            start_time: Some(10),
+            cliff_time: Some(50),
+            ..stream_terms(&payer_key, &payee_key, amount, 100)
+        },
+    );
+    sign_send_instruction(&mut ctx, instruction, vec![&payer_key]).await.unwrap();
+
+    let stream = get_stream_account(&mut ctx.banks_client, &stream_key).await;
+    assert_eq!(stream.start_time, 10);
+    assert_eq!(stream.cliff_time, 50);
+
+    ctx.warp_to_slot(40).unwrap();
+    let transaction = withdrawal_stream_transaction(
+        program_id,
+        &stream_key,
+        &payee_key,
+        &ctx.payer,
+        amount,
+        ctx.last_blockhash
+    );
+    ctx.banks_client.process_transaction(transaction).await.unwrap();
+
+    let stream = get_stream_account(&mut ctx.banks_client, &stream_key).await;
+    assert_eq!(stream.withdrawn_lamports, 0);
+
+    ctx.warp_to_slot(60).unwrap();
+    let transaction = withdrawal_stream_transaction(
+        program_id,
+        &stream_key,
+        &payee_key,
+        &ctx.payer,
+        amount + 1,
+        ctx.last_blockhash
+    );
+    ctx.banks_client.process_transaction(transaction).await.unwrap();
+
+    // The cliff has passed, so everything vested since the start is paid out
+    let stream = get_stream_account(&mut ctx.banks_client, &stream_key).await;
+    assert!(stream.withdrawn_lamports >= 500);
+}
+
+#[tokio::test]
+async fn should_not_create_stream_starting_in_the_past() {
+    let (program_id, program_test, payer_key, payee_key) = create_program_test();
+
+    let mut ctx = program_test.start_with_context().await;
+    ctx.warp_to_slot(20).unwrap();
+
+    let instruction = create_stream_instruction(
+        program_id,
+        StreamTerms {
+            start_time: Some(10),
+            ..stream_terms(&payer_key, &payee_key, 1000, 100)
+        },
+    );
+    let result = sign_send_instruction(&mut ctx, instruction, vec![&payer_key]).await;
+    assert_paystream_error(result, PaystreamError::InvalidStartTime);
+}
+
+#[test]
+fn should_vest_along_curves() {
+    let stream = |vesting_curve| StreamAccount {
+        amount_in_lamports: 1000,
+        remaining_lamports: 1000,
+        duration: 100,
+        vesting_curve,
+        ..StreamAccount::default()
+    };
+
+    let linear = stream(VestingCurve::Linear);
+    assert_eq!(linear.vested_amount(25).unwrap(), 250);
+
+    // Monthly style payroll, unlocking every 30
+    let stepped = stream(VestingCurve::Stepped { period: 30 });
+    assert_eq!(stepped.vested_amount(29).unwrap(), 0);
+    assert_eq!(stepped.vested_amount(30).unwrap(), 300);
+    assert_eq!(stepped.vested_amount(89).unwrap(), 600);
+    assert_eq!(stepped.vested_amount(99).unwrap(), 900);
+    assert_eq!(stepped.vested_amount(100).unwrap(), 1000);
+
+    let front = stream(VestingCurve::FrontWeighted { exponent: 2 });
+    assert_eq!(front.vested_amount(0).unwrap(), 0);
+    assert_eq!(front.vested_amount(50).unwrap(), 750);
+    assert_eq!(front.vested_amount(100).unwrap(), 1000);
+
+    let back = stream(VestingCurve::BackWeighted { exponent: 2 });
+    assert_eq!(back.vested_amount(50).unwrap(), 250);
+    assert_eq!(back.vested_amount(90).unwrap(), 810);
+    assert_eq!(back.vested_amount(100).unwrap(), 1000);
+
+    let schedule = stream(VestingCurve::Schedule(vec![
+        VestingCheckpoint { offset: 10, amount: 100 },
+        VestingCheckpoint { offset: 60, amount: 400 },
+    ]));
+    assert_eq!(schedule.vested_amount(9).unwrap(), 0);
+    assert_eq!(schedule.vested_amount(10).unwrap(), 100);
+    assert_eq!(schedule.vested_amount(59).unwrap(), 100);
+    assert_eq!(schedule.vested_amount(60).unwrap(), 400);
+    // Whatever the table leaves out vests at the end
+    assert_eq!(schedule.vested_amount(100).unwrap(), 1000);
+}
+
+#[test]
+fn should_validate_curves() {
+    assert!(VestingCurve::Linear.validate(1000, 100).is_ok());
+    assert!(VestingCurve::Stepped { period: 100 }.validate(1000, 100).is_ok());
+    assert!(VestingCurve::Stepped { period: 0 }.validate(1000, 100).is_err());
+    assert!(VestingCurve::Stepped { period: 101 }.validate(1000, 100).is_err());
+    assert!(VestingCurve::BackWeighted { exponent: 0 }.validate(1000, 100).is_err());
+    assert!(VestingCurve::FrontWeighted { exponent: 9 }.validate(1000, 100).is_err());
+    assert!(VestingCurve::Schedule(vec![]).validate(1000, 100).is_err());
+
+    let checkpoint = |offset, amount| VestingCheckpoint { offset, amount };
+    assert!(VestingCurve::Schedule(vec![checkpoint(10, 100), checkpoint(10, 200)])
+        .validate(1000, 100)
+        .is_err());
+    assert!(VestingCurve::Schedule(vec![checkpoint(10, 200), checkpoint(20, 100)])
+        .validate(1000, 100)
+        .is_err());
+    assert!(VestingCurve::Schedule(vec![checkpoint(101, 100)]).validate(1000, 100).is_err());
+    assert!(VestingCurve::Schedule(vec![checkpoint(50, 1001)]).validate(1000, 100).is_err());
+}
+
+#[tokio::test]
+async fn should_stream_on_schedule_curve() {
+    let (program_id, program_test, payer_key, payee_key) = create_program_test();
+    let stream_key = stream_address(program_id, &payer_key, &payee_key);
+    let amount = 1000;
+
+    let mut ctx = program_test.start_with_context().await;
+
+    let vesting_curve = VestingCurve::Schedule(vec![
+        VestingCheckpoint { offset: 10, amount: 100 },
+        VestingCheckpoint { offset: 50, amount: 600 },
+    ]);
+    let instruction = create_stream_instruction(
+        program_id,
+        StreamTerms {
+            start_time: Some(10),
+            vesting_curve: vesting_curve.clone(),
+            ..stream_terms(&payer_key, &payee_key, amount, 100)
+        },
+    );
+    sign_send_instruction(&mut ctx, instruction, vec![&payer_key]).await.unwrap();
+
+    // The account grows to hold the checkpoints
+    let account = ctx.banks_client.get_account(stream_key).await.unwrap().unwrap();
+    assert_eq!(account.data.len(), StreamAccount::LEN + 4 + 2 * 16);
+    let stream = get_stream_account(&mut ctx.banks_client, &stream_key).await;
+    assert_eq!(stream.vesting_curve, vesting_curve);
+
+    ctx.warp_to_slot(30).unwrap();
+    let transaction = withdrawal_stream_transaction(
+        program_id,
+        &stream_key,
+        &payee_key,
+        &ctx.payer,
+        amount,
+        ctx.last_blockhash
+    );
+    ctx.banks_client.process_transaction(transaction).await.unwrap();
+
+    let stream = get_stream_account(&mut ctx.banks_client, &stream_key).await;
+    assert_eq!(stream.withdrawn_lamports, 100);
+}
+
+#[tokio::test]
+async fn should_not_create_stream_with_invalid_curve() {
+    let (program_id, program_test, payer_key, payee_key) = create_program_test();
+
+    let mut ctx = program_test.start_with_context().await;
+
+    let instruction = create_stream_instruction(
+        program_id,
+        StreamTerms {
+            vesting_curve: VestingCurve::Stepped { period: 0 },
+            ..stream_terms(&payer_key, &payee_key, 1000, 100)
+        },
+    );
+    let result = sign_send_instruction(&mut ctx, instruction, vec![&payer_key]).await;
+    assert_paystream_error(result, PaystreamError::InvalidVestingCurve);
+}
+
+#[test]
+fn should_not_vest_while_paused() {
+    let mut stream = StreamAccount {
+        status: StreamStatus::Paused,
+        amount_in_lamports: 1000,
+        remaining_lamports: 1000,
+        duration: 100,
+        paused_at: 40,
+        ..StreamAccount::default()
+    };
+
+    // Vesting stands still at the pause
+    assert_eq!(stream.vested_amount(40).unwrap(), 400);
+    assert_eq!(stream.vested_amount(90).unwrap(), 400);
+
+    // Resumed at 60, so the end moves back by 20
+    stream.status = StreamStatus::Active;
+    stream.paused_duration = 20;
+    assert_eq!(stream.vested_amount(60).unwrap(), 400);
+    assert_eq!(stream.vested_amount(100).unwrap(), 800);
+    assert_eq!(stream.vested_amount(120).unwrap(), 1000);
+}
+
+#[tokio::test]
+async fn should_pause_and_resume_stream() {
+    let (program_id, program_test, payer_key, payee_key) = create_program_test();
+    let stream_key = stream_address(program_id, &payer_key, &payee_key);
+    let authority_key = Keypair::new();
+    let amount = 1000;
+
+    let mut ctx = program_test.start_with_context().await;
+
+    let instruction = create_stream_instruction(
+        program_id,
+        StreamTerms {
+            start_time: Some(10),
+            pause_authority: Some(authority_key.pubkey()),
+            ..stream_terms(&payer_key, &payee_key, amount, 100)
+        },
+    );
+    sign_send_instruction(&mut ctx, instruction, vec![&payer_key]).await.unwrap();
+
+    // Only the payer or the pause authority may pause
+    let instruction = pause(program_id, PaystreamInstruction::Pause {}, stream_key, payee_key.pubkey()).unwrap();
+    let result = sign_send_instruction(&mut ctx, instruction, vec![&payee_key]).await;
+    assert_paystream_error(result, PaystreamError::InvalidPauseAuthority);
+
+    ctx.warp_to_slot(30).unwrap();
+    let instruction = pause(program_id, PaystreamInstruction::Pause {}, stream_key, authority_key.pubkey()).unwrap();
+    sign_send_instruction(&mut ctx, instruction, vec![&authority_key]).await.unwrap();
+
+    let stream = get_stream_account(&mut ctx.banks_client, &stream_key).await;
+    assert_eq!(stream.status, StreamStatus::Paused);
+    let paused_at = stream.paused_at;
+
+    // Withdrawals while paused only get what vested before the pause
+    ctx.warp_to_slot(80).unwrap();
+    let transaction = withdrawal_stream_transaction(
+        program_id,
+        &stream_key,
+        &payee_key,
+        &ctx.payer,
+        amount,
+        ctx.last_blockhash
+    );
+    ctx.banks_client.process_transaction(transaction).await.unwrap();
+
+    let stream = get_stream_account(&mut ctx.banks_client, &stream_key).await;
+    assert_eq!(stream.withdrawn_lamports, (paused_at - 10) * 10);
+
+    let instruction = resume(program_id, PaystreamInstruction::Resume {}, stream_key, payer_key.pubkey()).unwrap();
+    sign_send_instruction(&mut ctx, instruction, vec![&payer_key]).await.unwrap();
+
+    let stream = get_stream_account(&mut ctx.banks_client, &stream_key).await;
+    assert_eq!(stream.status, StreamStatus::Active);
+    assert!(stream.paused_duration >= 80 - paused_at);
+
+    // Resuming twice fails
+    let instruction = resume(program_id, PaystreamInstruction::Resume {}, stream_key, authority_key.pubkey()).unwrap();
+    let result = sign_send_instruction(&mut ctx, instruction, vec![&authority_key]).await;
+    assert_paystream_error(result, PaystreamError::StreamNotPaused);
+}
+
+#[test]
+fn should_keep_accrued_amount_on_top_up() {
+    let stream = || StreamAccount {
+        status: StreamStatus::Active,
+        amount_in_lamports: 1000,
+        remaining_lamports: 1000,
+        duration: 100,
+        ..StreamAccount::default()
+    };
+
+    // Same rate of 10 per slot, for 50 more slots
+    let mut extended = stream();
+    extended.top_up(500, TopUpMode::ExtendDuration, 30).unwrap();
+    assert_eq!(extended.amount_in_lamports, 1500);
+    assert_eq!(extended.remaining_lamports, 1500);
+    assert_eq!(extended.vested_amount(30).unwrap(), 300);
+    assert_eq!(extended.vested_amount(90).unwrap(), 900);
+    assert_eq!(extended.vested_amount(149).unwrap(), 1490);
+    assert_eq!(extended.vested_amount(150).unwrap(), 1500);
+
+    // Same end, the unvested 700 plus 700 more over the last 70 slots
+    let mut faster = stream();
+    faster.top_up(700, TopUpMode::IncreaseRate, 30).unwrap();
+    assert_eq!(faster.vested_amount(30).unwrap(), 300);
+    assert_eq!(faster.vested_amount(65).unwrap(), 1000);
+    assert_eq!(faster.vested_amount(100).unwrap(), 1700);
+
+    // Nothing left to speed up once the stream ended
+    let mut ended = stream();
+    assert_eq!(
+        ended.top_up(700, TopUpMode::IncreaseRate, 120).unwrap_err() as u32,
+        PaystreamError::InvalidTopUp as u32
+    );
+    ended.top_up(500, TopUpMode::ExtendDuration, 120).unwrap();
+    assert_eq!(ended.vested_amount(120).unwrap(), 1000);
+    assert_eq!(ended.vested_amount(145).unwrap(), 1250);
+
+    let mut stepped = StreamAccount {
+        vesting_curve: VestingCurve::Stepped { period: 10 },
+        ..stream()
+    };
+    assert!(stepped.top_up(500, TopUpMode::ExtendDuration, 30).is_err());
+}
+
+#[tokio::test]
+async fn should_top_up_stream() {
+    let (program_id, program_test, payer_key, payee_key) = create_program_test();
+    let stream_key = stream_address(program_id, &payer_key, &payee_key);
+    let amount = 1000;
+
+    let mut ctx = program_test.start_with_context().await;
+
+    let transaction = create_stream_transaction(
+        program_id,
+        &payee_key,
+        &payer_key,
+        amount,
+        100,
+        &ctx.payer,
+        ctx.last_blockhash
+    );
+    ctx.banks_client.process_transaction(transaction).await.unwrap();
+    let stream = get_stream_account(&mut ctx.banks_client, &stream_key).await;
+    let balance = ctx.banks_client.get_balance(stream_key).await.unwrap();
+
+    ctx.warp_to_slot(stream.start_time + 30).unwrap();
+    let instruction = top_up(
+        program_id,
+        PaystreamInstruction::TopUp { amount: 500, mode: TopUpMode::ExtendDuration },
+        stream_key,
+        payer_key.pubkey(),
+    ).unwrap();
+    sign_send_instruction(&mut ctx, instruction, vec![&payer_key]).await.unwrap();
+
+    let topped_up = get_stream_account(&mut ctx.banks_client, &stream_key).await;
+    assert_eq!(topped_up.amount_in_lamports, 1500);
+    assert_eq!(topped_up.remaining_lamports, 1500);
+    assert_eq!(topped_up.rebased_amount, stream.vested_amount(topped_up.start_time).unwrap());
+    assert_eq!(topped_up.end_time().unwrap(), stream.end_time().unwrap() + 50);
+    assert_eq!(ctx.banks_client.get_balance(stream_key).await.unwrap(), balance + 500);
+
+    // Only the payer tops up
+    let instruction = top_up(
+        program_id,
+        PaystreamInstruction::TopUp { amount: 500, mode: TopUpMode::IncreaseRate },
+        stream_key,
+        payee_key.pubkey(),
+    ).unwrap();
+    let result = sign_send_instruction(&mut ctx, instruction, vec![&payee_key]).await;
+    assert_paystream_error(result, PaystreamError::InvalidPayer);
+}
+
+#[tokio::test]
+async fn should_transfer_payee() {
+    let (program_id, program_test, payer_key, payee_key) = create_program_test();
+    let stream_key = stream_address(program_id, &payer_key, &payee_key);
+    let new_payee_key = Keypair::new();
+    let amount = 1000;
+
+    let mut ctx = program_test.start_with_context().await;
+
+    let instruction = create_stream_instruction(
+        program_id,
+        StreamTerms {
+            transferable: true,
+            ..stream_terms(&payer_key, &payee_key, amount, 100)
+        },
+    );
+    sign_send_instruction(&mut ctx, instruction, vec![&payer_key]).await.unwrap();
+
+    let instruction = transfer_payee(
+        program_id,
+        PaystreamInstruction::TransferPayee { new_payee: new_payee_key.pubkey() },
+        stream_key,
+        payee_key.pubkey(),
+    ).unwrap();
+    sign_send_instruction(&mut ctx, instruction, vec![&payee_key]).await.unwrap();
+
+    let stream = get_stream_account(&mut ctx.banks_client, &stream_key).await;
+    assert_eq!(stream.payee_pubkey, new_payee_key.pubkey());
+
+    // The stream stays at the address of the payee it was created for
+    assert_eq!(stream.original_payee_pubkey, payee_key.pubkey());
+    assert_eq!(
+        find_stream_address(&program_id, &payer_key.pubkey(), &stream.original_payee_pubkey, stream.seed).0,
+        stream_key
+    );
+
+    // The old payee is no longer paid
+    ctx.warp_to_slot(50).unwrap();
+    let transaction = withdrawal_stream_transaction(
+        program_id,
+        &stream_key,
+        &payee_key,
+        &ctx.payer,
+        amount,
+        ctx.last_blockhash
+    );
+    let result = ctx.banks_client.process_transaction(transaction).await;
+    assert_paystream_error(result, PaystreamError::InvalidPayee);
+
+    let transaction = withdrawal_stream_transaction(
+        program_id,
+        &stream_key,
+        &new_payee_key,
+        &ctx.payer,
+        amount,
+        ctx.last_blockhash
+    );
+    ctx.banks_client.process_transaction(transaction).await.unwrap();
+
+    let stream = get_stream_account(&mut ctx.banks_client, &stream_key).await;
+    assert!(stream.withdrawn_lamports > 0);
+    assert_eq!(
+        ctx.banks_client.get_balance(new_payee_key.pubkey()).await.unwrap(),
+        stream.withdrawn_lamports
+    );
+}
+
+#[tokio::test]
+async fn should_not_transfer_payee_of_non_transferable_stream() {
+    let (program_id, program_test, payer_key, payee_key) = create_program_test();
+    let stream_key = stream_address(program_id, &payer_key, &payee_key);
+
+    let mut ctx = program_test.start_with_context().await;
+
+    let transaction = create_stream_transaction(
+        program_id,
+        &payee_key,
+        &payer_key,
+        1000,
+        100,
+        &ctx.payer,
+        ctx.last_blockhash
+    );
+    ctx.banks_client.process_transaction(transaction).await.unwrap();
+
+    let instruction = transfer_payee(
+        program_id,
+        PaystreamInstruction::TransferPayee { new_payee: Pubkey::new_unique() },
+        stream_key,
+        payee_key.pubkey(),
+    ).unwrap();
+    let result = sign_send_instruction(&mut ctx, instruction, vec![&payee_key]).await;
+    assert_paystream_error(result, PaystreamError::NotTransferable);
+}
+
+#[tokio::test]
+async fn should_withdraw_to_destination() {
+    let (program_id, program_test, payer_key, payee_key) = create_program_test();
+    let stream_key = stream_address(program_id, &payer_key, &payee_key);
+    let destination_key = Pubkey::new_unique();
+
+    let mut ctx = program_test.start_with_context().await;
+
+    let transaction = create_stream_transaction(
+        program_id,
+        &payee_key,
+        &payer_key,
+        1000,
+        100,
+        &ctx.payer,
+        ctx.last_blockhash
+    );
+    ctx.banks_client.process_transaction(transaction).await.unwrap();
+
+    ctx.warp_to_slot(50).unwrap();
+    let payee_balance = ctx.banks_client.get_balance(payee_key.pubkey()).await.unwrap();
+    let instruction = withdrawal(
+        program_id,
+        PaystreamInstruction::Withdrawal { amount: 200 },
+        stream_key,
+        payee_key.pubkey(),
+        Some(destination_key),
+    ).unwrap();
+    sign_send_instruction(&mut ctx, instruction, vec![&payee_key]).await.unwrap();
+
+    assert_eq!(ctx.banks_client.get_balance(destination_key).await.unwrap(), 200);
+    assert_eq!(ctx.banks_client.get_balance(payee_key.pubkey()).await.unwrap(), payee_balance);
+
+    let stream = get_stream_account(&mut ctx.banks_client, &stream_key).await;
+    assert_eq!(stream.withdrawn_lamports, 200);
+}
+
+#[tokio::test]
+async fn should_push_withdrawal_to_payee() {
+    let (program_id, program_test, payer_key, payee_key) = create_program_test();
+    let stream_key = stream_address(program_id, &payer_key, &payee_key);
+    let keeper_key = Keypair::new();
+    let crank_tip = 5;
+
+    let mut ctx = program_test.start_with_context().await;
+
+    let instruction = create_stream_instruction(
+        program_id,
+        StreamTerms {
+            crank_tip,
+            ..stream_terms(&payer_key, &payee_key, 10_000, 100)
+        },
+    );
+    sign_send_instruction(&mut ctx, instruction, vec![&payer_key]).await.unwrap();
+
+    // Nothing has vested yet, so there is nothing to push
+    let instruction = push_withdrawal(
+        program_id,
+        PaystreamInstruction::PushWithdrawal {},
+        stream_key,
+        keeper_key.pubkey(),
+        payee_key.pubkey(),
+    ).unwrap();
+    let result = sign_send_instruction(&mut ctx, instruction, vec![&keeper_key]).await;
+    assert_paystream_error(result, PaystreamError::NothingToWithdraw);
+
+    // Signed by the keeper only, the payee doesn't have to be around
+    ctx.warp_to_slot(50).unwrap();
+    let payee_balance = ctx.banks_client.get_balance(payee_key.pubkey()).await.unwrap();
+    let instruction = push_withdrawal(
+        program_id,
+        PaystreamInstruction::PushWithdrawal {},
+        stream_key,
+        keeper_key.pubkey(),
+        payee_key.pubkey(),
+    ).unwrap();
+    sign_send_instruction(&mut ctx, instruction, vec![&keeper_key]).await.unwrap();
+
+    let stream = get_stream_account(&mut ctx.banks_client, &stream_key).await;
+    assert!(stream.withdrawn_lamports > crank_tip);
+    assert_eq!(ctx.banks_client.get_balance(keeper_key.pubkey()).await.unwrap(), crank_tip);
+    assert_eq!(
+        ctx.banks_client.get_balance(payee_key.pubkey()).await.unwrap(),
+        payee_balance + stream.withdrawn_lamports - crank_tip
+    );
+
+    // Only the stored payee can be paid
+    let instruction = push_withdrawal(
+        program_id,
+        PaystreamInstruction::PushWithdrawal {},
+        stream_key,
+        keeper_key.pubkey(),
+        keeper_key.pubkey(),
+    ).unwrap();
+    let result = sign_send_instruction(&mut ctx, instruction, vec![&keeper_key]).await;
+    assert_paystream_error(result, PaystreamError::InvalidPayee);
+}
+
+#[tokio::test]
+async fn should_cap_crank_tip_of_payer_pushing_own_stream() {
+    let (program_id, program_test, payer_key, payee_key) = create_program_test();
+    let stream_key = stream_address(program_id, &payer_key, &payee_key);
+    let amount = 10_000_000;
+
+    let mut ctx = program_test.start_with_context().await;
+
+    // The tip can't take more than 1% of the stream
+    let instruction = create_stream_instruction(
+        program_id,
+        StreamTerms {
+            crank_tip: amount / 100 + 1,
+            ..stream_terms(&payer_key, &payee_key, amount, 1000)
+        },
+    );
+    let result = sign_send_instruction(&mut ctx, instruction, vec![&payer_key]).await;
+    assert_paystream_error(result, PaystreamError::InvalidCrankTip);
+
+    let crank_tip = amount / 1000;
+    let instruction = create_stream_instruction(
+        program_id,
+        StreamTerms {
+            crank_tip,
+            ..stream_terms(&payer_key, &payee_key, amount, 1000)
+        },
+    );
+    sign_send_instruction(&mut ctx, instruction, vec![&payer_key]).await.unwrap();
+
+    let push = push_withdrawal(
+        program_id,
+        PaystreamInstruction::PushWithdrawal {},
+        stream_key,
+        payer_key.pubkey(),
+        payee_key.pubkey(),
+    ).unwrap();
+
+    // Pushing early would hand the payer more than 1% of the payout
+    ctx.warp_to_slot(5).unwrap();
+    let result = sign_send_instruction(&mut ctx, push.clone(), vec![&payer_key]).await;
+    assert_paystream_error(result, PaystreamError::NothingToWithdraw);
+
+    ctx.warp_to_slot(500).unwrap();
+    let payer_balance = ctx.banks_client.get_balance(payer_key.pubkey()).await.unwrap();
+    let payee_balance = ctx.banks_client.get_balance(payee_key.pubkey()).await.unwrap();
+    sign_send_instruction(&mut ctx, push.clone(), vec![&payer_key]).await.unwrap();
+
+    let stream = get_stream_account(&mut ctx.banks_client, &stream_key).await;
+    assert_eq!(ctx.banks_client.get_balance(payer_key.pubkey()).await.unwrap(), payer_balance + crank_tip);
+    assert_eq!(
+        ctx.banks_client.get_balance(payee_key.pubkey()).await.unwrap(),
+        payee_balance + stream.withdrawn_lamports - crank_tip
+    );
+
+    // Pushing again right away would let the payer take the tip over and over
+    ctx.warp_to_slot(505).unwrap();
+    let result = sign_send_instruction(&mut ctx, push, vec![&payer_key]).await;
+    assert_paystream_error(result, PaystreamError::PushTooSoon);
+}
+
+fn recipient(pubkey: Pubkey, weight_bps: u16) -> Recipient {
+    Recipient {
+        pubkey,
+        weight_bps,
+        withdrawn_lamports: 0,
+    }
+}
+
+#[test]
+fn should_split_shares_by_weight() {
+    let stream = StreamAccount {
+        amount_in_lamports: 1001,
+        remaining_lamports: 1001,
+        duration: 100,
+        recipients: vec![
+            recipient(Pubkey::new_unique(), 5000),
+            recipient(Pubkey::new_unique(), 3333),
+            recipient(Pubkey::new_unique(), 1667),
+        ],
+        ..StreamAccount::default()
+    };
+    assert!(stream.validate_recipients().is_ok());
+
+    // The last recipient gets the rounding dust, so nothing is left over
+    let shares: Vec<u64> = (0..3)
+        .map(|index| stream.recipient_share(index, 1001).unwrap())
+        .collect();
+    assert_eq!(shares, vec![500, 333, 168]);
+    assert_eq!(stream.owed_to_recipients(100).unwrap(), shares);
+
+    let bad_weights = StreamAccount {
+        recipients: vec![recipient(Pubkey::new_unique(), 5000), recipient(Pubkey::new_unique(), 4000)],
+        ..StreamAccount::default()
+    };
+    assert!(bad_weights.validate_recipients().is_err());
+
+    let duplicate = Pubkey::new_unique();
+    let duplicates = StreamAccount {
+        recipients: vec![recipient(duplicate, 5000), recipient(duplicate, 5000)],
+        ..StreamAccount::default()
+    };
+    assert!(duplicates.validate_recipients().is_err());
+}
+
+#[tokio::test]
+async fn should_split_stream_between_recipients() {
+    let (program_id, program_test, payer_key, payee_key) = create_program_test();
+    let stream_key = stream_address(program_id, &payer_key, &payee_key);
+    let alice_key = Keypair::new();
+    let bob_key = Keypair::new();
+    let carol_key = Keypair::new();
+    let amount = 1000;
+
+    let mut ctx = program_test.start_with_context().await;
+
+    let instruction = create_stream_instruction(
+        program_id,
+        StreamTerms {
+            start_time: Some(10),
+            recipients: vec![
+                recipient(alice_key.pubkey(), 5000),
+                recipient(bob_key.pubkey(), 3000),
+                recipient(carol_key.pubkey(), 2000),
+            ],
+            ..stream_terms(&payer_key, &payee_key, amount, 100)
+        },
+    );
+    sign_send_instruction(&mut ctx, instruction, vec![&payer_key]).await.unwrap();
+
+    let account = ctx.banks_client.get_account(stream_key).await.unwrap().unwrap();
+    assert_eq!(account.data.len(), StreamAccount::LEN + 3 * 42);
+
+    // Each recipient only gets their own share, the payee nothing
+    ctx.warp_to_slot(60).unwrap();
+    for (key, withdrawn) in [(&alice_key, 250), (&bob_key, 150)] {
+        let transaction = withdrawal_stream_transaction(
+            program_id,
+            &stream_key,
+            key,
+            &ctx.payer,
+            amount,
+            ctx.last_blockhash
+        );
+        ctx.banks_client.process_transaction(transaction).await.unwrap();
+        assert_eq!(ctx.banks_client.get_balance(key.pubkey()).await.unwrap(), withdrawn);
+    }
+
+    let transaction = withdrawal_stream_transaction(
+        program_id,
+        &stream_key,
+        &payee_key,
+        &ctx.payer,
+        amount,
+        ctx.last_blockhash
+    );
+    let result = ctx.banks_client.process_transaction(transaction).await;
+    assert_paystream_error(result, PaystreamError::InvalidPayee);
+
+    // Cancelling pays out what is still owed to each recipient and refunds the rest
+    let instruction = cancel_split(
+        program_id,
+        PaystreamInstruction::Cancel {},
+        stream_key,
+        payee_key.pubkey(),
+        payer_key.pubkey(),
+        payer_key.pubkey(),
+        &[alice_key.pubkey(), bob_key.pubkey(), carol_key.pubkey()],
+    ).unwrap();
+    sign_send_instruction(&mut ctx, instruction, vec![&payer_key]).await.unwrap();
+
+    let stream = get_stream_account(&mut ctx.banks_client, &stream_key).await;
+    assert_eq!(stream.status, StreamStatus::Terminated);
+    assert_eq!(stream.recipients[0].withdrawn_lamports, 250);
+    assert_eq!(stream.recipients[1].withdrawn_lamports, 150);
+    assert_eq!(stream.recipients[2].withdrawn_lamports, 100);
+    assert_eq!(ctx.banks_client.get_balance(carol_key.pubkey()).await.unwrap(), 100);
+    assert_eq!(stream.refunded_lamports, 500);
+}
+
+#[tokio::test]
+async fn should_let_recipients_act_as_payee_of_split_stream() {
+    let (program_id, program_test, payer_key, payee_key) = create_program_test();
+    let stream_key = stream_address(program_id, &payer_key, &payee_key);
+    let alice_key = Keypair::new();
+    let bob_key = Keypair::new();
+    let recipient_keys = [alice_key.pubkey(), bob_key.pubkey()];
+
+    let mut ctx = program_test.start_with_context().await;
+
+    let instruction = create_stream_instruction(
+        program_id,
+        StreamTerms {
+            start_time: Some(10),
+            transferable: true,
+            recipients: vec![recipient(alice_key.pubkey(), 5000), recipient(bob_key.pubkey(), 5000)],
+            ..stream_terms(&payer_key, &payee_key, 10_000_000, 100)
+        },
+    );
+    sign_send_instruction(&mut ctx, instruction, vec![&payer_key]).await.unwrap();
+
+    // The payee of a split stream is paid nothing, so it neither cancels nor hands it over
+    let instruction = cancel_split(
+        program_id,
+        PaystreamInstruction::Cancel {},
+        stream_key,
+        payee_key.pubkey(),
+        payer_key.pubkey(),
+        payee_key.pubkey(),
+        &recipient_keys,
+    ).unwrap();
+    let result = sign_send_instruction(&mut ctx, instruction, vec![&payee_key]).await;
+    assert_paystream_error(result, PaystreamError::CancellationNotAllowed);
+
+    let instruction = transfer_payee(
+        program_id,
+        PaystreamInstruction::TransferPayee { new_payee: Pubkey::new_unique() },
+        stream_key,
+        payee_key.pubkey(),
+    ).unwrap();
+    let result = sign_send_instruction(&mut ctx, instruction, vec![&payee_key]).await;
+    assert_paystream_error(result, PaystreamError::InvalidPayee);
+
+    // A recipient signs for the payee side instead
+    let instruction = cancel_split(
+        program_id,
+        PaystreamInstruction::Cancel {},
+        stream_key,
+        alice_key.pubkey(),
+        payer_key.pubkey(),
+        alice_key.pubkey(),
+        &recipient_keys,
+    ).unwrap();
+    sign_send_instruction(&mut ctx, instruction, vec![&alice_key]).await.unwrap();
+
+    let stream = get_stream_account(&mut ctx.banks_client, &stream_key).await;
+    assert_eq!(stream.status, StreamStatus::Terminated);
+    assert_eq!(stream.refunded_lamports, 10_000_000);
+}
+
+#[tokio::test]
+async fn should_not_create_split_stream_with_invalid_weights() {
+    let (program_id, program_test, payer_key, payee_key) = create_program_test();
+
+    let mut ctx = program_test.start_with_context().await;
+
+    let instruction = create_stream_instruction(
+        program_id,
+        StreamTerms {
+            recipients: vec![
+                recipient(Pubkey::new_unique(), 6000),
+                recipient(Pubkey::new_unique(), 6000),
+            ],
+            ..stream_terms(&payer_key, &payee_key, 1000, 100)
+        },
+    );
+    let result = sign_send_instruction(&mut ctx, instruction, vec![&payer_key]).await;
+    assert_paystream_error(result, PaystreamError::InvalidRecipients);
+}
+
+fn batch_instruction(payer_pubkey: Pubkey, streams: Vec<BatchStream>) -> PaystreamInstruction {
+    PaystreamInstruction::CreateBatch {
+        terms: StreamTerms {
+            payer_pubkey,
+            ..StreamTerms::default()
+        },
+        streams,
+    }
+}
+
+fn batch_stream(payee_pubkey: Pubkey, amount: u64) -> BatchStream {
+    BatchStream {
+        payee_pubkey,
+        amount,
+        duration: 100,
+        seed: 0,
+        start_time: None,
+        cliff_time: None,
+    }
+}
+
+#[test]
+fn should_pack_batches_into_transactions() {
+    let program_id = Pubkey::new_unique();
+    let payer_key = Keypair::new();
+    let streams: Vec<BatchStream> = (0..200)
+        .map(|_| batch_stream(Pubkey::new_unique(), 1000))
+        .collect();
+
+    let instructions =
+        create_batches(program_id, batch_instruction(payer_key.pubkey(), streams.clone())).unwrap();
+    assert!(instructions.len() > 1);
+
+    let mut packed = vec![];
+    for instruction in &instructions {
+        let transaction = Transaction::new_with_payer(&[instruction.clone()], Some(&payer_key.pubkey()));
+        let size = 1 + 64 * transaction.signatures.len() + transaction.message.serialize().len();
+        assert!(size <= MAX_TRANSACTION_SIZE);
+
+        match PaystreamInstruction::unpack(&instruction.data).unwrap() {
+            PaystreamInstruction::CreateBatch { streams, .. } => {
+                assert!(streams.len() <= MAX_BATCH_STREAMS);
+                packed.extend(streams)
+            }
+            other => panic!("Expected a batch, got {:?}", other),
+        }
+    }
+    assert_eq!(packed, streams);
+}
+
+#[tokio::test]
+async fn should_create_batch_skipping_failed_streams() {
+    let (program_id, program_test, payer_key, _) = create_program_test();
+    let payee_keys = [Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique()];
+
+    let mut ctx = program_test.start_with_context().await;
+
+    // The empty stream and the one the payer can't afford are skipped, the others are still
+    // created
+    let instruction = create_batch(
+        program_id,
+        batch_instruction(
+            payer_key.pubkey(),
+            vec![
+                batch_stream(payee_keys[0], 1000),
+                batch_stream(payee_keys[1], 0),
+                batch_stream(payee_keys[2], 100_000_000),
+                batch_stream(payee_keys[3], 3000),
+            ],
+        ),
+    ).unwrap();
+    sign_send_instruction(&mut ctx, instruction, vec![&payer_key]).await.unwrap();
+
+    for (payee_key, amount) in payee_keys.iter().zip([Some(1000), None, None, Some(3000)]) {
+        let (stream_key, _) = find_stream_address(&program_id, &payer_key.pubkey(), payee_key, 0);
+        let account = ctx.banks_client.get_account(stream_key).await.unwrap();
+        match amount {
+            Some(amount) => {
+                let stream = StreamAccount::load(&account.unwrap().data).unwrap();
+                assert_eq!(stream.payee_pubkey, *payee_key);
+                assert_eq!(stream.amount_in_lamports, amount);
+            }
+            None => assert!(account.is_none()),
+        }
+    }
+}
+
+#[tokio::test]
+async fn should_create_full_batch_within_compute_budget() {
+    let (program_id, mut program_test, payer_key, _) = create_program_test();
+    // Only metered when running the BPF version
+    program_test.set_bpf_compute_max_units(MAX_COMPUTE_UNITS);
+    let payee_keys: Vec<Pubkey> = (0..=MAX_BATCH_STREAMS).map(|_| Pubkey::new_unique()).collect();
+
+    let mut ctx = program_test.start_with_context().await;
+
+    let streams = payee_keys.iter().map(|key| batch_stream(*key, 1_000_000)).collect();
+    let instruction = create_batch(program_id, batch_instruction(payer_key.pubkey(), streams)).unwrap();
+    let result = sign_send_instruction(&mut ctx, instruction, vec![&payer_key]).await;
+    assert_paystream_error(result, PaystreamError::BatchTooLarge);
+
+    let streams = payee_keys[1..].iter().map(|key| batch_stream(*key, 1_000_000)).collect();
+    let instruction = create_batch(program_id, batch_instruction(payer_key.pubkey(), streams)).unwrap();
+    sign_send_instruction(&mut ctx, instruction, vec![&payer_key]).await.unwrap();
+
+    for payee_key in &payee_keys[1..] {
+        let (stream_key, _) = find_stream_address(&program_id, &payer_key.pubkey(), payee_key, 0);
+        assert!(ctx.banks_client.get_account(stream_key).await.unwrap().is_some());
+    }
+}
+
+#[test]
+fn should_vest_recurring_stream_while_funded() {
+    let mut stream = StreamAccount {
+        status: StreamStatus::Active,
+        amount_in_lamports: 250,
+        remaining_lamports: 250,
+        duration: 10,
+        recurrence: Some(Recurrence {
+            period_amount: 100,
+            max_periods: 0,
+        }),
+        ..StreamAccount::default()
+    };
+
+    assert_eq!(stream.vested_amount(10).unwrap(), 100);
+    assert_eq!(stream.vested_amount(15).unwrap(), 150);
+    stream.update_solvency(15).unwrap();
+    assert_eq!(stream.status, StreamStatus::Active);
+
+    // Runs dry at 25, the payee can still claim everything deposited
+    assert_eq!(stream.vested_amount(40).unwrap(), 250);
+    stream.update_solvency(40).unwrap();
+    assert_eq!(stream.status, StreamStatus::Insolvent);
+
+    // Refilled, what accrued while insolvent is owed as well
+    stream.top_up(500, TopUpMode::ExtendDuration, 40).unwrap();
+    stream.update_solvency(40).unwrap();
+    assert_eq!(stream.status, StreamStatus::Active);
+    assert_eq!(stream.vested_amount(40).unwrap(), 400);
+    assert_eq!(stream.vested_amount(100).unwrap(), 750);
+
+    // Limited to 3 periods, so it can't be funded beyond 300
+    let mut limited = StreamAccount {
+        recurrence: Some(Recurrence {
+            period_amount: 100,
+            max_periods: 3,
+        }),
+        ..stream
+    };
+    limited.amount_in_lamports = 300;
+    assert_eq!(limited.vested_amount(1000).unwrap(), 300);
+    assert_eq!(limited.end_time().unwrap(), 30);
+    assert!(limited.top_up(1, TopUpMode::ExtendDuration, 40).is_err());
+}
+
+#[tokio::test]
+async fn should_refill_insolvent_recurring_stream() {
+    let (program_id, program_test, payer_key, payee_key) = create_program_test();
+    let stream_key = stream_address(program_id, &payer_key, &payee_key);
+    let period_amount = 1_000_000;
+
+    let mut ctx = program_test.start_with_context().await;
+
+    let instruction = create_stream_instruction(
+        program_id,
+        StreamTerms {
+            start_time: Some(10),
+            recurrence: Some(Recurrence {
+                period_amount,
+                max_periods: 0,
+            }),
+            ..stream_terms(&payer_key, &payee_key, 2 * period_amount, 10)
+        },
+    );
+    sign_send_instruction(&mut ctx, instruction, vec![&payer_key]).await.unwrap();
+
+    // Two periods were funded, four have passed
+    ctx.warp_to_slot(50).unwrap();
+    let transaction = withdrawal_stream_transaction(
+        program_id,
+        &stream_key,
+        &payee_key,
+        &ctx.payer,
+        10 * period_amount,
+        ctx.last_blockhash
+    );
+    ctx.banks_client.process_transaction(transaction).await.unwrap();
+
+    let stream = get_stream_account(&mut ctx.banks_client, &stream_key).await;
+    assert_eq!(stream.status, StreamStatus::Insolvent);
+    assert_eq!(stream.withdrawn_lamports, 2 * period_amount);
+    assert_eq!(stream.remaining_lamports, 0);
+
+    let instruction = top_up(
+        program_id,
+        PaystreamInstruction::TopUp { amount: 5 * period_amount, mode: TopUpMode::ExtendDuration },
+        stream_key,
+        payer_key.pubkey(),
+    ).unwrap();
+    sign_send_instruction(&mut ctx, instruction, vec![&payer_key]).await.unwrap();
+
+    let stream = get_stream_account(&mut ctx.banks_client, &stream_key).await;
+    assert_eq!(stream.status, StreamStatus::Active);
+
+    // The arrears from while it was insolvent are paid out too
+    let transaction = withdrawal_stream_transaction(
+        program_id,
+        &stream_key,
+        &payee_key,
+        &ctx.payer,
+        10 * period_amount + 1,
+        ctx.last_blockhash
+    );
+    ctx.banks_client.process_transaction(transaction).await.unwrap();
+
+    let stream = get_stream_account(&mut ctx.banks_client, &stream_key).await;
+    assert_eq!(stream.withdrawn_lamports, 4 * period_amount);
+    assert_eq!(stream.status, StreamStatus::Active);
+}
+
+#[test]
+fn should_let_authority_act_for_payer() {
+    let payer = Pubkey::new_unique();
+    let authority = Pubkey::new_unique();
+    let mut stream = StreamAccount {
+        payer_pubkey: payer,
+        ..StreamAccount::default()
+    };
+
+    // Without an authority only the payer acts, the default key is no authority
+    assert!(stream.acts_for_payer(&payer));
+    assert!(!stream.acts_for_payer(&authority));
+    assert!(!stream.acts_for_payer(&Pubkey::default()));
+
+    stream.authority = authority;
+    assert!(stream.acts_for_payer(&authority));
+    assert!(stream.can_pause(&authority));
+    assert!(!stream.acts_for_payer(&Pubkey::new_unique()));
+}
+
+#[tokio::test]
+async fn should_cancel_and_top_up_by_authority() {
+    let (program_id, mut program_test, payer_key, payee_key) = create_program_test();
+    let stream_key = stream_address(program_id, &payer_key, &payee_key);
+    let authority_key = Keypair::new();
+    program_test.add_account(
+        authority_key.pubkey(),
+        Account {
+            lamports: 50000000,
+            ..Account::default()
+        },
+    );
+
+    let mut ctx = program_test.start_with_context().await;
+
+    let instruction = create_stream_instruction(
+        program_id,
+        StreamTerms {
+            cancellation_policy: CancellationPolicy::PayerOnly,
+            authority: Some(authority_key.pubkey()),
+            ..stream_terms(&payer_key, &payee_key, 1_000_000, 100)
+        },
+    );
+    sign_send_instruction(&mut ctx, instruction, vec![&payer_key]).await.unwrap();
+    let stream = get_stream_account(&mut ctx.banks_client, &stream_key).await;
+    assert_eq!(stream.authority, authority_key.pubkey());
+
+    // The authority funds the top-up itself
+    ctx.warp_to_slot(stream.start_time + 30).unwrap();
+    let instruction = top_up(
+        program_id,
+        PaystreamInstruction::TopUp { amount: 500_000, mode: TopUpMode::ExtendDuration },
+        stream_key,
+        authority_key.pubkey(),
+    ).unwrap();
+    sign_send_instruction(&mut ctx, instruction, vec![&authority_key]).await.unwrap();
+    let topped_up = get_stream_account(&mut ctx.banks_client, &stream_key).await;
+    assert_eq!(topped_up.amount_in_lamports, 1_500_000);
+
+    // Anyone else can't sign for the payer
+    let stranger_key = Keypair::new();
+    let instruction = cancel(
+        program_id,
+        PaystreamInstruction::Cancel {},
+        stream_key,
+        payee_key.pubkey(),
+        payer_key.pubkey(),
+        stranger_key.pubkey(),
+    ).unwrap();
+    let result = sign_send_instruction(&mut ctx, instruction, vec![&stranger_key]).await;
+    assert!(matches!(
+        result,
+        Err(TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::MissingRequiredSignature,
+        )))
+    ));
+
+    // The authority only counts at its place after the accounts the stream expects
+    let mut instruction = cancel(
+        program_id,
+        PaystreamInstruction::Cancel {},
+        stream_key,
+        payee_key.pubkey(),
+        payer_key.pubkey(),
+        authority_key.pubkey(),
+    ).unwrap();
+    instruction.accounts.insert(4, AccountMeta::new_readonly(Pubkey::new_unique(), false));
+    let result = sign_send_instruction(&mut ctx, instruction, vec![&authority_key]).await;
+    assert!(matches!(
+        result,
+        Err(TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::MissingRequiredSignature,
+        )))
+    ));
+
+    // The authority cancels on a payer only policy, the refund still goes to the payer
+    let payer_balance = ctx.banks_client.get_balance(payer_key.pubkey()).await.unwrap();
+    let instruction = cancel(
+        program_id,
+        PaystreamInstruction::Cancel {},
+        stream_key,
+        payee_key.pubkey(),
+        payer_key.pubkey(),
+        authority_key.pubkey(),
+    ).unwrap();
+    sign_send_instruction(&mut ctx, instruction, vec![&authority_key]).await.unwrap();
+
+    let cancelled = get_stream_account(&mut ctx.banks_client, &stream_key).await;
+    assert_eq!(cancelled.status, StreamStatus::Terminated);
+    assert!(cancelled.refunded_lamports > 0);
+    assert_eq!(cancelled.refunded_lamports + cancelled.withdrawn_lamports, 1_500_000);
+    assert_eq!(
+        ctx.banks_client.get_balance(payer_key.pubkey()).await.unwrap(),
+        payer_balance + cancelled.refunded_lamports
+    );
+}
+
+fn legacy_stream(payer_pubkey: Pubkey, payee_pubkey: Pubkey) -> LegacyStreamAccount {
+    LegacyStreamAccount {
+        status: StreamStatus::Active as u8,
+        payee_pubkey,
+        payer_pubkey,
+        amount_in_lamports: 1_000_000,
+        remaining_lamports: 600_000,
+        duration_in_slots: 100,
+        start_timestamp_in_slots: 0,
+    }
+}
+
+#[test]
+fn should_store_and_load_versioned_layout() {
+    let stream = StreamAccount {
+        status: StreamStatus::Active,
+        amount_in_lamports: 1000,
+        remaining_lamports: 1000,
+        duration: 100,
+        ..StreamAccount::default()
+    };
+    let mut data = vec![0xff; stream.packed_len().unwrap()];
+    stream.store(&mut data).unwrap();
+    assert_eq!(data.len(), StreamAccount::LEN);
+    assert_eq!(data[..8], STREAM_DISCRIMINATOR);
+    assert_eq!(data[8], STREAM_VERSION);
+    assert!(data[data.len() - 64..].iter().all(|byte| *byte == 0));
+    assert_eq!(StreamAccount::load(&data).unwrap().amount_in_lamports, 1000);
+
+    // Too small an account, or a version from the future
+    assert!(stream.store(&mut data[..100]).is_err());
+    data[8] = STREAM_VERSION + 1;
+    assert!(StreamAccount::load(&data).is_err());
+
+    // The unversioned layout loads for migration only
+    let legacy = legacy_stream(Pubkey::new_unique(), Pubkey::new_unique()).try_to_vec().unwrap();
+    assert_eq!(legacy.len(), LegacyStreamAccount::LEN);
+    assert!(StreamAccount::load(&legacy).is_err());
+    let (migrated, version) = StreamAccount::load_any(&legacy).unwrap();
+    assert_eq!(version, 0);
+    assert_eq!(migrated.withdrawn_lamports, 400_000);
+    assert_eq!(migrated.cancellation_policy, CancellationPolicy::PayeeOnly);
+    assert_eq!(migrated.cliff_time, migrated.start_time);
+}
+
+#[tokio::test]
+async fn should_migrate_legacy_stream() {
+    let (program_id, mut program_test, payer_key, payee_key) = create_program_test();
+    let legacy_key = Pubkey::new_unique();
+    let legacy_lamports = Rent::default().minimum_balance(LegacyStreamAccount::LEN) + 600_000;
+    program_test.add_account(
+        legacy_key,
+        Account {
+            lamports: legacy_lamports,
+            data: legacy_stream(payer_key.pubkey(), payee_key.pubkey()).try_to_vec().unwrap(),
+            owner: program_id,
+            ..Account::default()
+        },
+    );
+    let stream_key = stream_address(program_id, &payer_key, &payee_key);
+
+    let mut ctx = program_test.start_with_context().await;
+
+    let transaction = withdrawal_stream_transaction(
+        program_id,
+        &legacy_key,
+        &payee_key,
+        &ctx.payer,
+        100,
+        ctx.last_blockhash,
+    );
+    let result = ctx.banks_client.process_transaction(transaction).await;
+    assert_paystream_error(result, PaystreamError::StreamNeedsMigration);
+
+    let payer_balance = ctx.banks_client.get_balance(payer_key.pubkey()).await.unwrap();
+    let instruction = migrate(
+        program_id,
+        PaystreamInstruction::Migrate { seed: 0 },
+        legacy_key,
+        payer_key.pubkey(),
+        Some(stream_key),
+    ).unwrap();
+    sign_send_instruction(&mut ctx, instruction, vec![&payer_key]).await.unwrap();
+
+    let stream = get_stream_account(&mut ctx.banks_client, &stream_key).await;
+    assert_eq!(stream.payee_pubkey, payee_key.pubkey());
+    assert_eq!(stream.remaining_lamports, 600_000);
+    assert_eq!(stream.withdrawn_lamports, 400_000);
+    let rent = ctx.banks_client.get_rent().await.unwrap();
+    let stream_rent = rent.minimum_balance(StreamAccount::LEN);
+    assert_eq!(ctx.banks_client.get_balance(stream_key).await.unwrap(), stream_rent + 600_000);
+    assert!(ctx.banks_client.get_account(legacy_key).await.unwrap().is_none());
+    assert_eq!(
+        ctx.banks_client.get_balance(payer_key.pubkey()).await.unwrap(),
+        payer_balance + legacy_lamports - 600_000 - stream_rent
+    );
+}
+
+#[tokio::test]
+async fn should_let_payee_migrate_and_withdraw() {
+    let (program_id, mut program_test, payer_key, payee_key) = create_program_test();
+    let legacy_key = Pubkey::new_unique();
+    let legacy_lamports = Rent::default().minimum_balance(LegacyStreamAccount::LEN) + 600_000;
+    program_test.add_account(
+        legacy_key,
+        Account {
+            lamports: legacy_lamports,
+            data: legacy_stream(payer_key.pubkey(), payee_key.pubkey()).try_to_vec().unwrap(),
+            owner: program_id,
+            ..Account::default()
+        },
+    );
+    let stream_key = find_stream_address(&program_id, &payer_key.pubkey(), &payee_key.pubkey(), 1).0;
+
+    let mut ctx = program_test.start_with_context().await;
+
+    // The payee pays the rent of the new account and gets that of the old one
+    let payee_balance = ctx.banks_client.get_balance(payee_key.pubkey()).await.unwrap();
+    let instruction = migrate(
+        program_id,
+        PaystreamInstruction::Migrate { seed: 1 },
+        legacy_key,
+        payee_key.pubkey(),
+        Some(stream_key),
+    ).unwrap();
+    sign_send_instruction(&mut ctx, instruction, vec![&payee_key]).await.unwrap();
+
+    let stream = get_stream_account(&mut ctx.banks_client, &stream_key).await;
+    assert_eq!(stream.payer_pubkey, payer_key.pubkey());
+    assert_eq!(stream.seed, 1);
+    assert!(ctx.banks_client.get_account(legacy_key).await.unwrap().is_none());
+    let rent = ctx.banks_client.get_rent().await.unwrap();
+    let stream_rent = rent.minimum_balance(StreamAccount::LEN);
+    let payee_balance = payee_balance + legacy_lamports - 600_000 - stream_rent;
+    assert_eq!(ctx.banks_client.get_balance(payee_key.pubkey()).await.unwrap(), payee_balance);
+
+    ctx.warp_to_slot(60).unwrap();
+    let transaction = withdrawal_stream_transaction(
+        program_id,
+        &stream_key,
+        &payee_key,
+        &ctx.payer,
+        100_000,
+        ctx.last_blockhash,
+    );
+    ctx.banks_client.process_transaction(transaction).await.unwrap();
+
+    let stream = get_stream_account(&mut ctx.banks_client, &stream_key).await;
+    assert_eq!(stream.withdrawn_lamports, 500_000);
+    assert_eq!(ctx.banks_client.get_balance(payee_key.pubkey()).await.unwrap(), payee_balance + 100_000);
+}
+
+#[test]
+fn should_only_allow_status_transitions_of_the_lifecycle() {
+    let mut stream = StreamAccount::default();
+    assert!(matches!(
+        stream.transition(StreamStatus::Paused),
+        Err(PaystreamError::InvalidStatusTransition)
+    ));
+    stream.transition(StreamStatus::Active).unwrap();
+    stream.transition(StreamStatus::Paused).unwrap();
+    stream.transition(StreamStatus::Active).unwrap();
+    stream.transition(StreamStatus::Terminated).unwrap();
+
+    // Terminated and completed streams are final
+    for status in [StreamStatus::Active, StreamStatus::Paused, StreamStatus::Completed] {
+        assert!(!StreamStatus::Terminated.can_become(status));
+        assert!(!StreamStatus::Completed.can_become(status));
+    }
+    assert!(!StreamStatus::Insolvent.can_become(StreamStatus::Paused));
+    assert!(!StreamStatus::Active.can_become(StreamStatus::Uninitialized));
+}
+
+#[test]
+fn should_reject_unknown_status_when_loading() {
+    let stream = StreamAccount {
+        status: StreamStatus::Paused,
+        duration: 100,
+        ..StreamAccount::default()
+    };
+    let mut data = vec![0; stream.packed_len().unwrap()];
+    stream.store(&mut data).unwrap();
+    assert_eq!(data[9], 4);
+    assert_eq!(StreamAccount::load(&data).unwrap().status, StreamStatus::Paused);
+
+    data[9] = 6;
+    assert!(StreamAccount::load(&data).is_err());
+}
+
+/// Deterministic key for the golden vectors, every byte set to `byte`
+fn golden_key(byte: u8) -> Pubkey {
+    Pubkey::new_from_array([byte; 32])
+}
+
+fn from_hex(hex: &str) -> Vec<u8> {
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+        .collect()
+}
+
+/// Golden vectors of the instruction encoding, to check clients in other languages against. Each
+/// instruction is followed by the hex encoding of each of its fields in order, starting with the
+/// tag.
+fn golden_instructions() -> Vec<(PaystreamInstruction, Vec<&'static str>)> {
+    vec![
+        (
+            PaystreamInstruction::Create {
+                terms: StreamTerms {
+                    payee_pubkey: golden_key(1),
+                    payer_pubkey: golden_key(2),
+                    amount: 1000,
+                    duration: 100,
+                    seed: 7,
+                    cancellation_policy: CancellationPolicy::PayerOnly,
+                    schedule_mode: ScheduleMode::Slot,
+                    start_time: Some(10),
+                    cliff_time: None,
+                    vesting_curve: VestingCurve::Linear,
+                    pause_authority: None,
+                    transferable: true,
+                    crank_tip: 5,
+                    recipients: vec![],
+                    recurrence: None,
+                    authority: Some(golden_key(3)),
+                },
+            },
+            vec![
+                "00", // tag
+                "0101010101010101010101010101010101010101010101010101010101010101", // terms.payee_pubkey
+                "0202020202020202020202020202020202020202020202020202020202020202", // terms.payer_pubkey
+                "e803000000000000", // terms.amount
+                "6400000000000000", // terms.duration
+                "0700000000000000", // terms.seed
+                "01", // terms.cancellation_policy
+                "00", // terms.schedule_mode
+                "010a00000000000000", // terms.start_time
+                "00", // terms.cliff_time
+                "00", // terms.vesting_curve
+                "00", // terms.pause_authority
+                "01", // terms.transferable
+                "0500000000000000", // terms.crank_tip
+                "00000000", // terms.recipients
+                "00", // terms.recurrence
+                "010303030303030303030303030303030303030303030303030303030303030303", // terms.authority
+            ],
+        ),
+        (
+            PaystreamInstruction::Withdrawal { amount: 500 },
+            vec![
+                "01", // tag
+                "f401000000000000", // amount
+            ],
+        ),
+        (
+            PaystreamInstruction::Cancel {},
+            vec![
+                "02", // tag
+            ],
+        ),
+        (
+            PaystreamInstruction::CreateTokenStream {
+                terms: StreamTerms {
+                    payee_pubkey: golden_key(1),
+                    payer_pubkey: golden_key(2),
+                    amount: 3000,
+                    duration: 30,
+                    seed: 0,
+                    cancellation_policy: CancellationPolicy::Irrevocable,
+                    schedule_mode: ScheduleMode::UnixTimestamp,
+                    start_time: None,
+                    cliff_time: Some(20),
+                    vesting_curve: VestingCurve::Schedule(vec![VestingCheckpoint { offset: 10, amount: 1000 }]),
+                    pause_authority: Some(golden_key(4)),
+                    transferable: false,
+                    crank_tip: 0,
+                    recipients: vec![recipient(golden_key(5), 10_000)],
+                    recurrence: Some(Recurrence { period_amount: 1000, max_periods: 3 }),
+                    authority: None,
+                },
+            },
+            vec![
+                "03", // tag
+                "0101010101010101010101010101010101010101010101010101010101010101", // terms.payee_pubkey
+                "0202020202020202020202020202020202020202020202020202020202020202", // terms.payer_pubkey
+                "b80b000000000000", // terms.amount
+                "1e00000000000000", // terms.duration
+                "0000000000000000", // terms.seed
+                "03", // terms.cancellation_policy
+                "01", // terms.schedule_mode
+                "00", // terms.start_time
+                "011400000000000000", // terms.cliff_time
+                "04010000000a00000000000000e803000000000000", // terms.vesting_curve
+                "010404040404040404040404040404040404040404040404040404040404040404", // terms.pause_authority
+                "00", // terms.transferable
+                "0000000000000000", // terms.crank_tip
+                "01000000050505050505050505050505050505050505050505050505050505050505050510270000000000000000", // terms.recipients
+                "01e8030000000000000300000000000000", // terms.recurrence
+                "00", // terms.authority
+            ],
+        ),
+        (
+            PaystreamInstruction::Close {},
+            vec![
+                "04", // tag
+            ],
+        ),
+        (
+            PaystreamInstruction::Pause {},
+            vec![
+                "05", // tag
+            ],
+        ),
+        (
+            PaystreamInstruction::Resume {},
+            vec![
+                "06", // tag
+            ],
+        ),
+        (
+            PaystreamInstruction::TopUp { amount: 250, mode: TopUpMode::IncreaseRate },
+            vec![
+                "07", // tag
+                "fa00000000000000", // amount
+                "01", // mode
+            ],
+        ),
+        (
+            PaystreamInstruction::TransferPayee { new_payee: golden_key(6) },
+            vec![
+                "08", // tag
+                "0606060606060606060606060606060606060606060606060606060606060606", // new_payee
+            ],
+        ),
+        (
+            PaystreamInstruction::PushWithdrawal {},
+            vec![
+                "09", // tag
+            ],
+        ),
+        (
+            PaystreamInstruction::CreateBatch {
+                terms: StreamTerms {
+                    payer_pubkey: golden_key(2),
+                    vesting_curve: VestingCurve::Stepped { period: 10 },
+                    ..StreamTerms::default()
+                },
+                streams: vec![BatchStream {
+                    payee_pubkey: golden_key(1),
+                    amount: 1000,
+                    duration: 100,
+                    seed: 1,
+                    start_time: None,
+                    cliff_time: None,
+                }],
+            },
+            vec![
+                "0a", // tag
+                "0000000000000000000000000000000000000000000000000000000000000000", // terms.payee_pubkey
+                "0202020202020202020202020202020202020202020202020202020202020202", // terms.payer_pubkey
+                "0000000000000000", // terms.amount
+                "0000000000000000", // terms.duration
+                "0000000000000000", // terms.seed
+                "00", // terms.cancellation_policy
+                "00", // terms.schedule_mode
+                "00", // terms.start_time
+                "00", // terms.cliff_time
+                "010a00000000000000", // terms.vesting_curve
+                "00", // terms.pause_authority
+                "00", // terms.transferable
+                "0000000000000000", // terms.crank_tip
+                "00000000", // terms.recipients
+                "00", // terms.recurrence
+                "00", // terms.authority
+                "010000000101010101010101010101010101010101010101010101010101010101010101e803000000000000640000000000000001000000000000000000", // streams
+            ],
+        ),
+        (
+            PaystreamInstruction::Migrate { seed: 2 },
+            vec![
+                "0b", // tag
+                "0200000000000000", // seed
+            ],
+        ),
+    ]
+}
+
+#[test]
+fn should_encode_instructions_as_golden_vectors() {
+    for (instruction, fields) in golden_instructions() {
+        let data = from_hex(&fields.concat());
+        assert_eq!(instruction.pack(), data, "{:?}", instruction);
+        assert_eq!(PaystreamInstruction::unpack(&data).unwrap(), instruction);
+    }
+}
+
+#[test]
+fn should_reject_malformed_instruction_data() {
+    for (instruction, _) in golden_instructions() {
+        let data = instruction.pack();
+        // Every truncation and any trailing byte is an error
+        for len in 0..data.len() {
+            assert!(PaystreamInstruction::unpack(&data[..len]).is_err(), "{:?}", instruction);
+        }
+        let mut trailing = data.clone();
+        trailing.push(0);
+        assert!(PaystreamInstruction::unpack(&trailing).is_err(), "{:?}", instruction);
+    }
+
+    // Unknown tags, and unknown values of nested enums such as the mode of a top-up
+    assert!(PaystreamInstruction::unpack(&[12]).is_err());
+    assert!(PaystreamInstruction::unpack(&from_hex("070100000000000002")).is_err());
+}
+
+fn event_log(event: &PaystreamEvent) -> String {
+    format!("Program log: {}{}", EVENT_LOG_PREFIX, base64::encode(event.pack()))
+}
+
+#[test]
+fn should_decode_events_from_program_logs() {
+    let program_id = Pubkey::new_unique();
+    let other_program_id = Pubkey::new_unique();
+    let stream = Pubkey::new_unique();
+    let created = PaystreamEvent::StreamCreated {
+        stream,
+        payer: Pubkey::new_unique(),
+        payee: Pubkey::new_unique(),
+        mint: Pubkey::default(),
+        amount: 1000,
+        start_time: 10,
+        duration: 100,
+    };
+    let withdrawn = PaystreamEvent::Withdrawn {
+        stream,
+        payee: Pubkey::new_unique(),
+        destination: Pubkey::new_unique(),
+        amount: 400,
+        crank_tip: 0,
+    };
+    let logs = vec![
+        format!("Program {} invoke [1]", program_id),
+        "Program log: [Paystream] Created stream account".to_string(),
+        event_log(&created),
+        // Events logged by another program called along the way don't count
+        format!("Program {} invoke [2]", other_program_id),
+        event_log(&PaystreamEvent::Closed { stream }),
+        format!("Program {} consumed 1000 of 190000 compute units", other_program_id),
+        format!("Program {} success", other_program_id),
+        event_log(&withdrawn),
+        format!("Program {} consumed 10000 of 200000 compute units", program_id),
+        format!("Program {} success", program_id),
+    ];
+
+    assert_eq!(decode_logs(&program_id, &logs).unwrap(), vec![created, withdrawn.clone()]);
+    assert_eq!(decode_logs(&other_program_id, &logs).unwrap().len(), 1);
+
+    // Lines of other versions or garbled ones are errors, anything else isn't an event
+    let mut data = withdrawn.pack();
+    assert_eq!(data[0], EVENT_VERSION);
+    data[0] = EVENT_VERSION + 1;
+    let line = format!("{}{}", EVENT_LOG_PREFIX, base64::encode(&data));
+    assert_eq!(decode_log(&line), Some(Err(EventError::UnknownVersion(EVENT_VERSION + 1))));
+    assert_eq!(decode_log("Program log: PAYSTREAM_EVENT ???"), Some(Err(EventError::InvalidBase64)));
+    assert!(decode_log("Program log: [Paystream] Stream completed").is_none());
+}
+
+#[test]
+fn should_only_report_status_changes_on_the_side() {
+    let stream = Pubkey::new_unique();
+    assert_eq!(
+        PaystreamEvent::status_changed(stream, StreamStatus::Active, StreamStatus::Completed),
+        Some(PaystreamEvent::Completed { stream })
+    );
+    assert_eq!(
+        PaystreamEvent::status_changed(stream, StreamStatus::Insolvent, StreamStatus::Active),
+        Some(PaystreamEvent::Solvent { stream })
+    );
+    assert_eq!(PaystreamEvent::status_changed(stream, StreamStatus::Active, StreamStatus::Active), None);
+}